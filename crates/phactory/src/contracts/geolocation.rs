@@ -31,6 +31,18 @@ pub enum Error {
     Unimplemented,
 }
 
+impl Error {
+    /// A stable HTTP-like status code of the error, for the clients which can't match the variants
+    pub fn code(&self) -> u16 {
+        match self {
+            Error::NoRecord => 404,
+            Error::NotAuthorized => 403,
+            Error::UnavailableCityName => 404,
+            Error::Unimplemented => 501,
+        }
+    }
+}
+
 #[derive(Encode, Decode, Debug, Clone)]
 pub enum Request {
     GetGeolocationInfo { account: AccountId },
@@ -118,6 +130,14 @@ impl contracts::NativeContract for Geolocation {
         }
     }
 
+    fn error_code(response: &Result<Response, Error>) -> Option<u16> {
+        response.as_ref().err().map(Error::code)
+    }
+
+    fn encode_response(response: &Result<Response, Error>) -> Vec<u8> {
+        contracts::coded_response(response, Error::code)
+    }
+
     fn handle_query(
         &mut self,
         origin: Option<&chain::AccountId>,
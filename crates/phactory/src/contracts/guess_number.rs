@@ -2,7 +2,6 @@ use anyhow::Result;
 use log::info;
 use parity_scale_codec::{Decode, Encode};
 use phala_mq::MessageOrigin;
-use sp_core::hashing;
 use std::convert::TryInto;
 
 use super::{TransactionError, TransactionResult};
@@ -78,6 +77,16 @@ pub enum Error {
     NotAuthorized,
 }
 
+impl Error {
+    /// A stable HTTP-like status code of the error, for the clients which can't match the variants
+    pub fn code(&self) -> u16 {
+        match self {
+            Error::OriginUnavailable => 401,
+            Error::NotAuthorized => 403,
+        }
+    }
+}
+
 impl GuessNumber {
     pub fn new() -> Self {
         GuessNumber {
@@ -86,12 +95,12 @@ impl GuessNumber {
         }
     }
 
-    /// Generate random number using on-chain block height
+    /// Generate random number using on-chain entropy
     ///
     /// As mentioned above, off-chain random generation can break the state consistency across multiple instances of the
     /// same contract
     pub fn gen_random_number(context: &NativeContext) -> RandomNumber {
-        let hash = hashing::blake2_256(&context.block.block_number.to_be_bytes());
+        let hash = contracts::chain_random(context, b"guess_number");
         u32::from_be_bytes(
             hash[..4]
                 .try_into()
@@ -153,6 +162,14 @@ impl contracts::NativeContract for GuessNumber {
         }
     }
 
+    fn error_code(response: &Result<Response, Error>) -> Option<u16> {
+        response.as_ref().err().map(Error::code)
+    }
+
+    fn encode_response(response: &Result<Response, Error>) -> Vec<u8> {
+        contracts::coded_response(response, Error::code)
+    }
+
     /// Handle a direct Query and respond to it. It shouldn't modify the contract state.
     ///
     /// # Arguments
@@ -9,7 +9,7 @@ use crate::types::{deopaque_query, OpaqueError, OpaqueQuery, OpaqueReply};
 use anyhow::{Context, Error, Result};
 use chain::AccountId;
 use parity_scale_codec::{Decode, Encode};
-use phala_mq::{MessageOrigin, Sr25519MessageChannel as MessageChannel};
+use phala_mq::{Message, MessageOrigin, Receiver, Sr25519MessageChannel as MessageChannel};
 
 pub mod assets;
 pub mod balances;
@@ -36,12 +36,121 @@ pub fn account_id_from_hex(s: &str) -> Result<AccountId> {
         .map_err(|err| anyhow::anyhow!("Failed to convert AccountId: {:?}", err))
 }
 
+/// Reject the Commands to a paused contract, except the `pause_switch` Command which is needed to resume it
+///
+/// The Queries are not affected, so that a paused contract remains readable.
+pub fn ensure_not_paused(paused: bool, pause_switch: bool) -> TransactionResult {
+    if paused && !pause_switch {
+        return Err(TransactionError::Paused);
+    }
+    Ok(())
+}
+
+/// 32 random bytes derived from the on-chain entropy of the current block and `seed`
+///
+/// The contracts must not use an off-chain random generator, since the replicas would then reach different states.
+/// This one gives the same bytes to all the replicas handling the same block. The block entropy is public once the
+/// block is known, so a value meant to be unguessable needs a `seed` holding some secret contract state, and a
+/// `seed` unique to each use to get different values within a block.
+///
+/// The entropy is the state root, which is unavailable before the chain state is synced, e.g. at the genesis block or
+/// with the empty storage of a test. The Commands which can't do with a guessable value must fail with
+/// `EntropyUnavailable` then:
+///
+/// - Pastebin `CreateShareToken`, a token is the only permission needed to read a private post.
+pub fn try_chain_random(
+    context: &NativeContext,
+    seed: &[u8],
+) -> Result<[u8; 32], TransactionError> {
+    let root = context.block.storage.root();
+    if *root == Default::default() {
+        return Err(TransactionError::EntropyUnavailable);
+    }
+    let entropy = (context.block.block_number, root, seed);
+    Ok(sp_core::hashing::blake2_256(&entropy.encode()))
+}
+
+/// Like `try_chain_random`, but derived from the block number and `seed` only if the block carries no entropy
+///
+/// The fallback is as deterministic as the entropy but guessable by anyone, so it only suits the values which are
+/// fine to be predicted, e.g. the number of the GuessNumber demo.
+pub fn chain_random(context: &NativeContext, seed: &[u8]) -> [u8; 32] {
+    try_chain_random(context, seed).unwrap_or_else(|_| {
+        sp_core::hashing::blake2_256(&(context.block.block_number, seed).encode())
+    })
+}
+
+/// The on-chain timestamp of the current block in seconds
+///
+/// It is the `pallet_timestamp` value of the block, so unlike the local clock it is the same for all the replicas
+/// handling the block. The Commands must take their "now" from here, e.g. for the creation times, expiries and rate
+/// limits they store; the local clock is only fit for the Queries, which don't change the contract state.
+pub fn block_timestamp(context: &NativeContext) -> u64 {
+    context.block.now_ms / 1000
+}
+
+/// A privileged Command handled by a contract, recorded in its audit log
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    /// The block the Command is handled in, the same for all the replicas unlike the local time
+    pub block: chain::BlockNumber,
+    pub sender: AccountId,
+    /// The name of the Command, without its arguments which may hold secrets
+    pub action: String,
+}
+
+/// The maximum number of entries kept in the audit log of a contract
+pub const MAX_AUDIT_ENTRIES: usize = 256;
+
+/// Append an entry to the audit log of a contract, dropping the oldest ones beyond `MAX_AUDIT_ENTRIES`
+pub fn record_audit(audit_log: &mut Vec<AuditEntry>, entry: AuditEntry) {
+    audit_log.push(entry);
+    let excess = audit_log.len().saturating_sub(MAX_AUDIT_ENTRIES);
+    audit_log.drain(..excess);
+}
+
+/// The `limit` entries of the audit log of a contract starting from the `offset`-th one, oldest first
+pub fn audit_log_page(audit_log: &[AuditEntry], offset: u32, limit: u32) -> Vec<AuditEntry> {
+    audit_log
+        .iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .cloned()
+        .collect()
+}
+
+/// A failed Query in the reply of a contract, with the numeric code of its error
+///
+/// The code is encoded before the error, so that the clients which can't decode the error variants, e.g. the
+/// non-Rust ones, can still read it.
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+pub struct QueryError<E> {
+    pub code: u16,
+    pub error: E,
+}
+
+/// Encode a Query response as a `Result<T, QueryError<E>>`, for `NativeContract::encode_response`
+pub fn coded_response<T: Encode, E: Encode>(
+    response: &Result<T, E>,
+    code: impl Fn(&E) -> u16,
+) -> Vec<u8> {
+    response
+        .as_ref()
+        .map_err(|error| QueryError {
+            code: code(error),
+            error,
+        })
+        .encode()
+}
+
 pub use support::*;
 mod support {
     use core::convert::TryInto;
 
     use super::*;
     use crate::types::BlockInfo;
+    use phala_types::messaging::{AckStatus, CommandAck};
+    use sp_core::hashing::blake2_256;
 
     pub struct ExecuteEnv<'a> {
         pub block: &'a mut BlockInfo<'a>,
@@ -49,6 +158,8 @@ mod support {
 
     pub struct NativeContext<'a, 'b> {
         pub block: &'a mut BlockInfo<'b>,
+        /// The contract handling the block
+        contract: ContractId32,
         mq: &'a MessageChannel,
         #[allow(unused)] // TODO.kevin: remove this.
         secret_mq: SecretMessageChannel<'a>,
@@ -58,12 +169,39 @@ mod support {
         pub fn mq(&self) -> &MessageChannel {
             self.mq
         }
+
+        /// A handle to the egress channel to be moved into the result process of an `AsyncSideTask`
+        pub fn outcome_reporter(&self) -> OutcomeReporter {
+            OutcomeReporter {
+                mq: self.mq.clone(),
+                topic: outcome_topic(id256(self.contract)),
+            }
+        }
+    }
+
+    /// Sends the typed outcomes of the side tasks of a contract to its `outcome_topic`
+    ///
+    /// It should only be used in the side task result process, which is run at the same block by all the replicas.
+    /// The process must report exactly one outcome whatever the result of the task, so that all the replicas send
+    /// the same number of egress messages; the result itself differs between the replicas, e.g. with a request
+    /// answered in time on some of them only.
+    #[derive(Clone)]
+    pub struct OutcomeReporter {
+        mq: MessageChannel,
+        topic: Vec<u8>,
+    }
+
+    impl OutcomeReporter {
+        pub fn report<M: Encode>(&self, outcome: &M) {
+            self.mq.sendto(outcome, self.topic.clone());
+        }
     }
 
     pub trait Contract {
         fn id(&self) -> ContractId;
         fn handle_query(
             &mut self,
+            storage: &crate::Storage,
             origin: Option<&chain::AccountId>,
             req: OpaqueQuery,
         ) -> Result<OpaqueReply, OpaqueError>;
@@ -84,11 +222,43 @@ mod support {
         ) -> TransactionResult {
             Ok(())
         }
+        /// Whether to send a `CommandAck` to the egress channel after handling each Command
+        fn ack_commands(&self) -> bool {
+            false
+        }
+        /// Called once per block after all the Commands of the block are handled, for the block-driven state changes
+        fn on_block_end(&mut self, _context: &mut NativeContext) {}
+        /// Handle an outcome sent by the result process of a side task of the contract with an `OutcomeReporter`
+        ///
+        /// The outcomes come back from the chain, which only accepts one of the copies sent by the replicas, so all
+        /// the replicas handle the same outcome at the same block. The state depending on the side task results must
+        /// be updated here rather than in the result process, where each replica only sees the result it got.
+        fn handle_outcome(&mut self, _context: &mut NativeContext, _outcome: &[u8]) {}
         fn handle_query(
             &mut self,
             origin: Option<&chain::AccountId>,
             req: Self::QReq,
         ) -> Self::QResp;
+        /// Like `handle_query`, for the contracts with Queries which read the chain state, e.g. a balance
+        ///
+        /// `storage` is the chain state after the last synced block, which may be ahead of the last block handled by
+        /// the contract. The Queries are answered by `handle_query` by default.
+        fn handle_query_at(
+            &mut self,
+            _storage: &crate::Storage,
+            origin: Option<&chain::AccountId>,
+            req: Self::QReq,
+        ) -> Self::QResp {
+            self.handle_query(origin, req)
+        }
+        /// The numeric code of the error if the Query response is one
+        fn error_code(_response: &Self::QResp) -> Option<u16> {
+            None
+        }
+        /// Encode the Query response into the reply, the contracts with error codes put them in with `coded_response`
+        fn encode_response(response: &Self::QResp) -> Vec<u8> {
+            response.encode()
+        }
     }
 
     pub struct NativeCompatContract<Con, Cmd, CmdWrp, CmdPlr, QReq, QResp>
@@ -103,6 +273,7 @@ mod support {
         contract: Con,
         send_mq: MessageChannel,
         cmd_rcv_mq: PeelingReceiver<Cmd, CmdWrp, CmdPlr>,
+        outcome_rcv_mq: Receiver<(u64, Message)>,
         ecdh_key: KeyPair,
     }
 
@@ -121,12 +292,14 @@ mod support {
             contract: Con,
             send_mq: MessageChannel,
             cmd_rcv_mq: PeelingReceiver<Cmd, CmdWrp, CmdPlr>,
+            outcome_rcv_mq: Receiver<(u64, Message)>,
             ecdh_key: KeyPair,
         ) -> Self {
             NativeCompatContract {
                 contract,
                 send_mq,
                 cmd_rcv_mq,
+                outcome_rcv_mq,
                 ecdh_key,
             }
         }
@@ -135,7 +308,7 @@ mod support {
     impl<Con, Cmd, CmdWrp, CmdPlr, QReq, QResp> Contract
         for NativeCompatContract<Con, Cmd, CmdWrp, CmdPlr, QReq, QResp>
     where
-        Cmd: Decode + Debug + Send + Sync,
+        Cmd: Decode + Encode + Debug + Send + Sync,
         CmdWrp: Decode + Debug + Send + Sync,
         CmdPlr: Peeler<Wrp = CmdWrp, Msg = Cmd> + Send + Sync,
         QReq: Decode + Debug,
@@ -149,11 +322,22 @@ mod support {
 
         fn handle_query(
             &mut self,
+            storage: &crate::Storage,
             origin: Option<&runtime::AccountId>,
             req: OpaqueQuery,
         ) -> Result<OpaqueReply, OpaqueError> {
-            let response = self.contract.handle_query(origin, deopaque_query(req)?);
-            Ok(response.encode())
+            let response = self
+                .contract
+                .handle_query_at(storage, origin, deopaque_query(req)?);
+            if let Some(code) = Con::error_code(&response) {
+                info!(
+                    "Query to {} failed with code {}: {:?}",
+                    self.id(),
+                    code,
+                    response
+                );
+            }
+            Ok(Con::encode_response(&response))
         }
 
         fn process_messages(&mut self, env: &mut ExecuteEnv) {
@@ -168,6 +352,7 @@ mod support {
             let secret_mq = SecretMessageChannel::new(&self.ecdh_key, &self.send_mq, &key_map);
             let mut context = NativeContext {
                 block: env.block,
+                contract: self.contract.id(),
                 mq: &self.send_mq,
                 secret_mq,
             };
@@ -175,17 +360,46 @@ mod support {
                 let ok = phala_mq::select! {
                     next_cmd = self.cmd_rcv_mq => match next_cmd {
                         Ok((_, cmd, origin)) => {
-                            let _status = self.contract.handle_command(&mut context, origin, cmd);
+                            let command_hash = self
+                                .contract
+                                .ack_commands()
+                                .then(|| blake2_256(&cmd.encode()));
+                            let status = self.contract.handle_command(&mut context, origin, cmd);
+                            if let Some(command_hash) = command_hash {
+                                context.mq().send(&CommandAck {
+                                    contract: id256(self.contract.id()),
+                                    command_hash,
+                                    result: status.map_err(|err| AckStatus {
+                                        code: err.code(),
+                                        message: format!("{:?}", err),
+                                    }),
+                                });
+                            }
                         }
                         Err(e) => {
                             error!("Read command failed [{}]: {:?}", self.id(), e);
                         }
                     },
+                    next_outcome = self.outcome_rcv_mq => match next_outcome {
+                        Ok((_, message)) => {
+                            // anyone can push a message to the topic, only the ones of the contract itself count
+                            let own_origin = MessageOrigin::native_contract(self.contract.id());
+                            if message.sender == own_origin {
+                                self.contract.handle_outcome(&mut context, &message.payload);
+                            } else {
+                                warn!("Outcome to {} from {:?} ignored", self.id(), message.sender);
+                            }
+                        }
+                        Err(e) => {
+                            error!("Read outcome failed [{}]: {:?}", self.id(), e);
+                        }
+                    },
                 };
                 if ok.is_none() {
                     break;
                 }
             }
+            self.contract.on_block_end(&mut context);
         }
     }
 }
@@ -1,17 +1,26 @@
 use anyhow::Result;
 use log::info;
-use parity_scale_codec::{Decode, Encode};
+use parity_scale_codec::{Decode, DecodeAll, Encode, Error as CodecError, Input};
 use phala_mq::MessageOrigin;
 use sp_core::hashing;
+use sp_runtime::traits::Verify;
+use sp_runtime::MultiSignature;
 use std::convert::TryInto;
-use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::Bound;
+
+use chrono::{FixedOffset, TimeZone};
 
 use super::{TransactionError, TransactionResult};
 use crate::contracts;
-use crate::contracts::{AccountId, NativeContext};
+use crate::contracts::{block_timestamp, AccountId, AuditEntry, NativeContext};
+use crate::storage::StorageExt as _;
 extern crate runtime as chain;
 
-use phala_types::messaging::PastebinCommand;
+use phala_types::messaging::{
+    ContentEncoding, EncryptionMeta, ExpiryAction, NewPost, PastebinCommand, PastebinEvent,
+};
 
 /// Contract Overview
 ///
@@ -22,7 +31,7 @@ use phala_types::messaging::PastebinCommand;
 /// the according contract. Such design ensures the state consistency across multiple instances of the same contract, since
 /// all the instances will reach the same state after replaying all the Commands.
 /// Such property limits the use of random generator in our contracts: you can only generate random with on-chain entropy,
-/// because off-chain random generation can break the state consistency. We will show an example in the following code.
+/// because off-chain random generation can break the state consistency. See the share tokens in the following code.
 ///
 /// The Queries are not allowed to change the state of contract. They are directly sent to contract through the local rpc
 /// endpoint. Since they are off-chain requests, they can be sent and then real-time processed.
@@ -44,6 +53,75 @@ type PostTitle = String;
 
 type CreateOn = u64;
 
+type UpdateOn = u64;
+
+type ExpireAt = u64;
+
+/// Custom annotations of a post, a `BTreeMap` keeps the encoding deterministic
+type Metadata = BTreeMap<String, String>;
+
+/// A secret granting read access to a single post
+type ShareToken = [u8; 32];
+
+// The defaults of `PastebinConfig`
+
+/// The maximum size of the content of a single post, before compression
+const MAX_CONTENT_BYTES: usize = 64 * 1024;
+
+/// The maximum number of posts in a single `CreatePosts` batch
+const MAX_BATCH_SIZE: usize = 100;
+
+/// The maximum number of posts returned in one page of a listing query
+const MAX_PAGE_SIZE: u32 = 100;
+
+/// The bounds of the metadata of a single post
+const MAX_METADATA_ENTRIES: usize = 16;
+const MAX_METADATA_KEY_BYTES: usize = 64;
+const MAX_METADATA_VALUE_BYTES: usize = 256;
+
+/// The bounds of the previous contents kept for each post, the oldest ones are dropped first
+const MAX_HISTORY_ENTRIES: usize = 10;
+const MAX_HISTORY_BYTES: usize = 64 * 1024;
+
+/// The maximum number of accounts a post can be shared with
+const MAX_READERS: usize = 32;
+
+/// The maximum number of posts a single owner can pin
+const MAX_PINNED_POSTS: usize = 10;
+
+/// The maximum length of a post id, and the characters allowed in it besides the ASCII letters and digits
+const MAX_ID_LEN: usize = 64;
+const ID_EXTRA_CHARS: &[char] = &['_', '-'];
+
+/// How far the timestamp of a signed Query can be from the current time, in seconds
+const SIGNATURE_VALIDITY_SECS: u64 = 5 * 60;
+
+/// The default number of posts a single owner can hold
+const DEFAULT_MAX_POSTS_PER_OWNER: u32 = 1000;
+
+/// How long a deleted post stays in the trash before being purged, in seconds
+const TRASH_RETENTION_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// The maximum encoded size of the posts returned in one page of `ExportPosts`
+const MAX_EXPORT_BYTES: usize = 1024 * 1024;
+
+/// The maximum size of a chunk returned by `QueryPostChunk`
+const MAX_CHUNK_BYTES: u32 = 16 * 1024;
+
+/// The maximum number of changed lines returned by `QueryDiff`
+const MAX_DIFF_LINES: usize = 1000;
+
+/// The bounds of the words blocked in the public posts
+const MAX_BLOCKED_WORDS: usize = 256;
+const MAX_BLOCKED_WORD_BYTES: usize = 64;
+
+/// Contents not smaller than this are stored compressed
+const COMPRESSION_THRESHOLD: usize = 1024;
+
+/// The wall-clock time in seconds
+///
+/// It differs across the replicas, so it must only be used by Queries and never be written to the contract state.
+/// Commands should use `block_timestamp` instead.
 fn now() -> u64 {
     use std::time::SystemTime;
     let now = SystemTime::now()
@@ -52,23 +130,431 @@ fn now() -> u64 {
     now.as_secs()
 }
 
+/// The name of a Command in the audit log, only the root Commands are recorded
+fn audit_action(cmd: &Command) -> Option<&'static str> {
+    match cmd {
+        Command::SetPaused { .. } => Some("SetPaused"),
+        Command::TransferContractOwner { .. } => Some("TransferContractOwner"),
+        Command::SetBlockedWords { .. } => Some("SetBlockedWords"),
+        Command::SetPostQuota { .. } => Some("SetPostQuota"),
+        Command::SetMaxReaders { .. } => Some("SetMaxReaders"),
+        Command::SetMaxTtl { .. } => Some("SetMaxTtl"),
+        Command::SetDefaultTtl { .. } => Some("SetDefaultTtl"),
+        Command::SetMinBalanceToPost { .. } => Some("SetMinBalanceToPost"),
+        _ => None,
+    }
+}
+
+/// The current layout version of `Post`
+///
+/// `Post` is SCALE-encoded with `version` as its first field, so a decoder can tell which fields an encoded post
+/// carries before reading them. To keep older encodings decodable when `Post` evolves:
+///
+/// 1. append the new field to the end of `Post`, never reorder or remove fields;
+/// 2. bump `POST_VERSION` and record the change in the history below;
+/// 3. in `Post::decode`, only read the new field when the encoded version is recent enough, otherwise leave it to
+///    its default;
+/// 4. if the plain default is not sensible, derive the value from the older fields in `Post::migrate`.
+///
+/// History:
+/// - 1: the first versioned layout
+/// - 2: `compressed` and `content_len`, `content` holds the deflate-compressed bytes when `compressed` is set
+/// - 3: `metadata`
+/// - 4: `readable_by` holds a list of accounts instead of a single one, an exception to the rule 1 handled by the
+///   decoder
+/// - 5: `history`
+/// - 6: `encryption`
+/// - 7: `deleted_at`
+/// - 8: `on_expiry`
+/// - 9: `parent_id`
+/// - 10: `max_reads` and `read_count`
+/// - 11: `pinned`
+/// - 12: `notify_reads`
+/// - 13: `content_encoding`
+const POST_VERSION: u8 = 13;
+
 // Post state for each bin
-#[derive(Encode, Decode, Debug, Clone, Default)]
+#[derive(Encode, Debug, Clone, Default)]
 pub struct Post {
+    version: u8,
     id: PostId,
-    content: PostContent,
+    /// The UTF-8 content, or its deflate-compressed bytes if `compressed` is set
+    ///
+    /// It has the same SCALE encoding as `PostContent`. Use `Post::content` to read it.
+    content: Vec<u8>,
     owner: AccountId,
     is_private: bool,
-    readable_by: AccountId, //Vec<H256>, /// FIXME: cannot infer accountid on command
+    readable_by: Vec<AccountId>,
     created_on: CreateOn,
     title: PostTitle,
+    updated_on: UpdateOn,
+    expires_at: Option<ExpireAt>,
+    compressed: bool,
+    /// The size of the uncompressed content
+    content_len: u32,
+    metadata: Metadata,
+    /// The previous contents with the time they were set, oldest first
+    history: Vec<(UpdateOn, PostContent)>,
+    /// Set if the content is the base64 ciphertext of the post, which the contract can't read
+    encryption: Option<EncryptionMeta>,
+    /// Set when the post is moved to the trash by `DeletePost`, it is purged `trash_retention_secs` later
+    deleted_at: Option<u64>,
+    /// Whether the post is kept or removed once it expires
+    on_expiry: ExpiryAction,
+    /// The post this one replies to
+    parent_id: Option<PostId>,
+    /// The number of reads recorded by `RecordView` after which the post can no longer be read
+    max_reads: Option<u64>,
+    read_count: u64,
+    /// Set by the owner to list the post first in `PostsByOwner`
+    pinned: bool,
+    /// Whether the reads reported by `RecordAccess` are notified to the owner with a `PastebinEvent::AccessNotice`
+    notify_reads: bool,
+    /// How the content encodes the bytes of the post, checked unless the post is encrypted
+    content_encoding: ContentEncoding,
+}
+
+impl Decode for Post {
+    fn decode<I: Input>(input: &mut I) -> Result<Self, CodecError> {
+        let version = u8::decode(input)?;
+        if version > POST_VERSION {
+            return Err("Unsupported Post version".into());
+        }
+        let mut post = Post {
+            version,
+            id: Decode::decode(input)?,
+            content: Decode::decode(input)?,
+            owner: Decode::decode(input)?,
+            is_private: Decode::decode(input)?,
+            readable_by: if version >= 4 {
+                Decode::decode(input)?
+            } else {
+                // the default account was used for "no reader"
+                let reader = AccountId::decode(input)?;
+                if reader == AccountId::default() { vec![] } else { vec![reader] }
+            },
+            created_on: Decode::decode(input)?,
+            title: Decode::decode(input)?,
+            updated_on: Decode::decode(input)?,
+            expires_at: Decode::decode(input)?,
+            compressed: if version >= 2 { Decode::decode(input)? } else { false },
+            content_len: if version >= 2 { Decode::decode(input)? } else { 0 },
+            metadata: if version >= 3 { Decode::decode(input)? } else { Default::default() },
+            history: if version >= 5 { Decode::decode(input)? } else { Default::default() },
+            encryption: if version >= 6 { Decode::decode(input)? } else { None },
+            deleted_at: if version >= 7 { Decode::decode(input)? } else { None },
+            on_expiry: if version >= 8 { Decode::decode(input)? } else { ExpiryAction::Hide },
+            parent_id: if version >= 9 { Decode::decode(input)? } else { None },
+            max_reads: if version >= 10 { Decode::decode(input)? } else { None },
+            read_count: if version >= 10 { Decode::decode(input)? } else { 0 },
+            pinned: if version >= 11 { Decode::decode(input)? } else { false },
+            notify_reads: if version >= 12 { Decode::decode(input)? } else { false },
+            content_encoding: if version >= 13 { Decode::decode(input)? } else { ContentEncoding::Utf8 },
+        };
+        post.migrate();
+        Ok(post)
+    }
+}
+
+impl Post {
+    /// Upgrade a post decoded from an older layout to the current one
+    fn migrate(&mut self) {
+        if self.version < 2 {
+            self.content_len = self.content.len() as u32;
+        }
+        self.version = POST_VERSION;
+    }
+
+    /// Store the content, compressed if it is large and compresses well
+    fn set_content(&mut self, content: PostContent) {
+        self.content_len = content.len() as u32;
+        self.compressed = false;
+        self.content = content.into_bytes();
+        if self.content.len() >= COMPRESSION_THRESHOLD {
+            let compressed = miniz_oxide::deflate::compress_to_vec(&self.content, 6);
+            if compressed.len() < self.content.len() {
+                self.content = compressed;
+                self.compressed = true;
+            }
+        }
+    }
+
+    /// Replace the content, keeping the current one in the bounded history
+    fn edit(&mut self, content: PostContent, updated_on: UpdateOn, config: &PastebinConfig) {
+        self.history.push((self.updated_on, self.content()));
+        let mut history_bytes: usize = self.history.iter().map(|(_, old)| old.len()).sum();
+        while self.history.len() > config.max_history_entries
+            || history_bytes > config.max_history_bytes
+        {
+            let (_, dropped) = self.history.remove(0);
+            history_bytes -= dropped.len();
+        }
+        self.set_content(content);
+        self.updated_on = updated_on;
+    }
+
+    /// The uncompressed content of a post not created by the contract, `None` if it is malformed or larger than
+    /// `max_bytes`
+    fn untrusted_content(&self, max_bytes: usize) -> Option<PostContent> {
+        let bytes = if self.compressed {
+            let bytes =
+                miniz_oxide::inflate::decompress_to_vec_with_limit(&self.content, max_bytes + 1).ok()?;
+            if bytes.len() > max_bytes {
+                return None;
+            }
+            bytes
+        } else {
+            self.content.clone()
+        };
+        String::from_utf8(bytes).ok()
+    }
+
+    /// The uncompressed content
+    fn content(&self) -> PostContent {
+        let bytes = if self.compressed {
+            // no need for a size limit, the content is compressed by the contract within `max_content_bytes`
+            miniz_oxide::inflate::decompress_to_vec(&self.content)
+                .expect("content is compressed by the contract; qed.")
+        } else {
+            self.content.clone()
+        };
+        String::from_utf8(bytes).expect("content is set from a String; qed.")
+    }
+
+    /// The content at `version`, the index of a previous content in `history` or `history.len()` for the current one
+    fn content_at(&self, version: u32) -> Option<PostContent> {
+        let version = version as usize;
+        if version == self.history.len() {
+            Some(self.content())
+        } else {
+            self.history.get(version).map(|(_, old)| old.clone())
+        }
+    }
+
+    /// A copy of the post with the uncompressed content, to be returned to the clients
+    fn decompressed(&self) -> Post {
+        let mut post = self.clone();
+        if post.compressed {
+            post.content = self.content().into_bytes();
+            post.compressed = false;
+        }
+        post
+    }
+
+    /// Drop the readers unless `reader` is the owner
+    ///
+    /// The readers can read the content, but only the owner can see whom it is shared with, like in `QueryAccessList`.
+    fn hide_readers_from(&mut self, reader: Option<&AccountId>) {
+        if reader != Some(&self.owner) {
+            self.readable_by.clear();
+        }
+    }
+
+    /// The post returned to `reader`, with the uncompressed content and the readers hidden from the non-owners
+    fn view_for(&self, reader: Option<&AccountId>) -> Post {
+        let mut post = self.decompressed();
+        post.hide_readers_from(reader);
+        post
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at <= now)
+    }
+
+    /// Whether all the reads allowed by `max_reads` are recorded
+    fn is_exhausted(&self) -> bool {
+        matches!(self.max_reads, Some(max_reads) if self.read_count >= max_reads)
+    }
+
+    fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    /// Whether the post can still be read at `now`, i.e. it is not expired, deleted nor exhausted
+    fn is_live(&self, now: u64) -> bool {
+        !self.is_expired(now) && !self.is_deleted() && !self.is_exhausted()
+    }
+
+    fn is_readable_by(&self, account: &AccountId) -> bool {
+        !self.is_private || account == &self.owner || self.readable_by.contains(account)
+    }
+
+    fn summary(&self) -> PostSummary {
+        PostSummary {
+            id: self.id.clone(),
+            owner: self.owner.clone(),
+            is_private: self.is_private,
+            title: self.title.clone(),
+            created_on: self.created_on,
+            updated_on: self.updated_on,
+            expires_at: self.expires_at,
+            pinned: self.pinned,
+        }
+    }
+}
+
+/// The listing view of a post, without its content
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct PostSummary {
+    id: PostId,
+    owner: AccountId,
+    is_private: bool,
+    title: PostTitle,
+    created_on: CreateOn,
+    updated_on: UpdateOn,
+    expires_at: Option<ExpireAt>,
+    pinned: bool,
+}
+
+/// The deployment settings of the contract, read-only once it is created
+///
+/// The settings which can be changed by Commands are copied into the contract state.
+#[derive(Debug, Clone)]
+pub struct PastebinConfig {
+    /// The privileged account allowed to change the contract settings, until transferred by `TransferContractOwner`
+    pub root: AccountId,
+    /// The maximum size of the content of a single post, before compression
+    pub max_content_bytes: usize,
+    /// The maximum number of posts in a single `CreatePosts` batch
+    pub max_batch_size: usize,
+    /// The maximum number of posts returned in one page of a listing query
+    pub max_page_size: u32,
+    /// The maximum encoded size of the posts returned in one page of `ExportPosts`
+    pub max_export_bytes: usize,
+    /// The maximum size of a chunk returned by `QueryPostChunk`
+    pub max_chunk_bytes: u32,
+    /// The maximum number of changed lines returned by `QueryDiff`
+    pub max_diff_lines: usize,
+    /// The bounds of the metadata of a single post
+    pub max_metadata_entries: usize,
+    pub max_metadata_key_bytes: usize,
+    pub max_metadata_value_bytes: usize,
+    /// The bounds of the previous contents kept for each post
+    pub max_history_entries: usize,
+    pub max_history_bytes: usize,
+    /// The maximum number of accounts a post can be shared with, until changed by `SetMaxReaders`
+    pub max_readers: usize,
+    /// The maximum number of posts a single owner can pin
+    pub max_pinned_posts: usize,
+    /// How far the timestamp of a signed Query can be from the current time, in seconds
+    pub signature_validity_secs: u64,
+    /// The number of posts a single owner can hold until changed by `SetPostQuota`
+    pub max_posts_per_owner: u32,
+    /// The lifetime in seconds of the posts created without an expiry, until changed by `SetDefaultTtl`
+    pub default_ttl: Option<u64>,
+    /// The maximum lifetime in seconds of the new posts, and whether to reject a longer one instead of shortening it,
+    /// until changed by `SetMaxTtl`
+    pub max_ttl: Option<u64>,
+    pub reject_longer_ttl: bool,
+    /// How long a deleted post can be restored before being purged, in seconds
+    pub trash_retention_secs: u64,
+    pub id_policy: IdPolicy,
+    /// The free balance an account needs on chain to own new posts, until changed by `SetMinBalanceToPost`
+    pub min_balance_to_post: Option<chain::Balance>,
+}
+
+/// The shape of the post ids, which are put in URLs by the clients
+///
+/// An id is 1 to `max_len` ASCII letters, digits or `extra_chars`. Only the new ids are checked, the posts created
+/// before keep theirs.
+#[derive(Debug, Clone)]
+pub struct IdPolicy {
+    pub max_len: usize,
+    pub extra_chars: Vec<char>,
+}
+
+impl Default for IdPolicy {
+    /// `[a-zA-Z0-9_-]{1,64}`
+    fn default() -> Self {
+        IdPolicy {
+            max_len: MAX_ID_LEN,
+            extra_chars: ID_EXTRA_CHARS.to_vec(),
+        }
+    }
+}
+
+impl IdPolicy {
+    fn allows(&self, id: &str) -> bool {
+        !id.is_empty()
+            && id.len() <= self.max_len
+            && id.chars().all(|c| c.is_ascii_alphanumeric() || self.extra_chars.contains(&c))
+    }
+}
+
+impl Default for PastebinConfig {
+    /// The settings for dev mode and tests, with Alice as the root account
+    fn default() -> Self {
+        PastebinConfig {
+            root: contracts::account_id_from_hex(ALICE)
+                .expect("should not failed with valid address; qed."),
+            max_content_bytes: MAX_CONTENT_BYTES,
+            max_batch_size: MAX_BATCH_SIZE,
+            max_page_size: MAX_PAGE_SIZE,
+            max_export_bytes: MAX_EXPORT_BYTES,
+            max_chunk_bytes: MAX_CHUNK_BYTES,
+            max_diff_lines: MAX_DIFF_LINES,
+            max_metadata_entries: MAX_METADATA_ENTRIES,
+            max_metadata_key_bytes: MAX_METADATA_KEY_BYTES,
+            max_metadata_value_bytes: MAX_METADATA_VALUE_BYTES,
+            max_history_entries: MAX_HISTORY_ENTRIES,
+            max_history_bytes: MAX_HISTORY_BYTES,
+            max_readers: MAX_READERS,
+            max_pinned_posts: MAX_PINNED_POSTS,
+            signature_validity_secs: SIGNATURE_VALIDITY_SECS,
+            max_posts_per_owner: DEFAULT_MAX_POSTS_PER_OWNER,
+            default_ttl: None,
+            max_ttl: None,
+            reject_longer_ttl: false,
+            trash_retention_secs: TRASH_RETENTION_SECS,
+            id_policy: Default::default(),
+            min_balance_to_post: None,
+        }
+    }
 }
 
 /// Contract state
 #[derive(Debug, Default)]
 pub struct Pastebin {
-    /// TODO: change this with Vector and add index
-    post_by_id: HashMap<PostId, Post>,
+    config: PastebinConfig,
+    /// The privileged account allowed to change the contract settings
+    root: AccountId,
+    /// Ordered by id, so that the listing is deterministic and can be paged with an id cursor
+    post_by_id: BTreeMap<PostId, Post>,
+    /// Number of posts stored for each owner, to bound the state growth per user
+    ///
+    /// It is incremented on create and import, and decremented when a post is removed from the storage, so a deleted
+    /// post keeps counting until it is purged from the trash.
+    post_count_by_owner: HashMap<AccountId, u32>,
+    max_posts_per_owner: u32,
+    /// The maximum number of accounts a post can be shared with
+    max_readers: usize,
+    /// The lifetime in seconds of the posts created without an expiry
+    default_ttl: Option<u64>,
+    /// The maximum lifetime in seconds of the new posts
+    max_ttl: Option<u64>,
+    /// Whether to reject a post living longer than `max_ttl` instead of shortening its lifetime
+    reject_longer_ttl: bool,
+    /// The deleted posts by their `deleted_at`, to purge them in order
+    trash: BTreeSet<(u64, PostId)>,
+    /// The posts readable with each share token, at most one token per post
+    share_tokens: BTreeMap<ShareToken, PostId>,
+    /// The posts to remove once expired by their `expires_at`, to remove them in order
+    expiring: BTreeSet<(ExpireAt, PostId)>,
+    /// The lowercase words the public posts must not contain
+    blocked_words: BTreeSet<String>,
+    /// Whether the contract is paused by the root account
+    paused: bool,
+    /// The latest root Commands, oldest first
+    audit_log: Vec<AuditEntry>,
+    /// The free balance an account needs on chain to own new posts, `None` to let any account post
+    min_balance_to_post: Option<chain::Balance>,
+    /// The posts moved out of `post_by_id` by `ArchivePost`, as `archive_blob`s with their owner
+    ///
+    /// They keep their id and count towards the quota of their owner, but are left out of all the Queries until
+    /// restored by `UnarchivePost`. The owner is kept aside so that the blobs needn't be decoded to be told apart.
+    archived: BTreeMap<PostId, (AccountId, Vec<u8>)>,
+    /// The number and the time in seconds of the last block handled, updated at the end of each block
+    last_block: (chain::BlockNumber, u64),
 }
 
 /// The Queries to this contract
@@ -79,148 +565,3458 @@ pub struct Pastebin {
 pub enum Request {
     /// Query the content of pastebin
     QueryPost { id: PostId },
+    /// Query the number of live posts, the number of posts held by the sender and the per-owner limit
+    QueryStats,
+    /// Check whether a `CreatePost` of the post would succeed, without committing it
+    ///
+    /// The post goes through all the checks of `CreatePost`, with the balance of the sender of the Query and the time
+    /// of the worker in place of the ones of the block the Command would be handled in.
+    ValidateCreate(NewPost),
+    /// Check whether a post has expired without fetching its content
+    IsExpired { id: PostId },
+    /// Query `length` characters of the content starting from the `offset`-th character
+    QueryPostSlice {
+        id: PostId,
+        offset: u32,
+        length: u32,
+    },
+    /// Query the content of pastebin as `account`, proven by its signature over `signed_read_payload(id, timestamp)`
+    /// instead of the origin of the Query
+    QueryPostSigned {
+        id: PostId,
+        account: AccountId,
+        signature: MultiSignature,
+        timestamp: u64,
+    },
+    /// Query the accounts a post is shared with, only allowed for the post owner
+    QueryAccessList { id: PostId },
+    /// List the public posts in id order, starting after the `cursor` id
+    PublicFeed {
+        cursor: Option<PostId>,
+        limit: u32,
+    },
+    /// List the posts of `owner` visible to the sender, the pinned ones first, each group ordered by creation time
+    PostsByOwner {
+        owner: AccountId,
+        offset: u32,
+        limit: u32,
+    },
+    /// Query the version of this contract
+    Version,
+    /// Query the current time of the worker and the last block it handled, for the clients to align the timestamps
+    /// of their expiries and signed Queries
+    ///
+    /// The Queries check the expiry and the signature timestamps against `wall_clock`, the local time of the worker,
+    /// which may differ between the workers. The Commands use the time of the block they are handled in, which is the
+    /// same for all of them, and `block_number` and `block_time` are the canonical ones of the last handled block.
+    ServerTime,
+    /// Query the previous contents of a post, oldest first
+    QueryHistory { id: PostId },
+    /// Query the content of pastebin with its timestamps formatted in ISO-8601 at the given UTC offset
+    QueryPostWithDates { id: PostId, utc_offset_minutes: i32 },
+    /// Query the owner of a post without its content
+    ///
+    /// The owner is not a secret, it is returned even for private posts the sender cannot read.
+    QueryOwnerOf { id: PostId },
+    /// Query the content of pastebin by its title among the posts of `owner`
+    ///
+    /// The titles are not unique, the most recently created post wins, and the greatest id among the posts created
+    /// at the same time.
+    QueryByTitle { owner: AccountId, title: PostTitle },
+    /// Export the posts of `owner` in id order starting after the `cursor` id, only allowed for the owner
+    ///
+    /// The posts are returned as stored, including the expired ones but neither the deleted nor the exhausted ones,
+    /// to be restored by `ImportPosts`.
+    ExportPosts {
+        owner: AccountId,
+        cursor: Option<PostId>,
+    },
+    /// Check whether a post id is taken, e.g. before a `CreatePost`
+    ///
+    /// It needs no origin and checks no permission: the ids are chosen by their authors and are not secrets, like in
+    /// `QueryOwnerOf`. An expired post that is still stored, or a deleted one not purged yet, keeps its id taken.
+    Exists { id: PostId },
+    /// List the posts created within `[from, to]` visible to the sender, ordered by creation time
+    PostsInRange {
+        from: CreateOn,
+        to: CreateOn,
+        offset: u32,
+        limit: u32,
+    },
+    /// Query the content of pastebin with a share token minted by its owner
+    ///
+    /// It needs no origin, the token is the permission. An unknown or revoked token finds nothing.
+    QueryPostByToken { token: ShareToken },
+    /// Query the share token of a post, only allowed for the post owner
+    QueryShareToken { id: PostId },
+    /// Query the `chunk_index`-th piece of `chunk_size` bytes of the content, for the posts too large for one response
+    ///
+    /// The chunks split the UTF-8 bytes, the client joins them before decoding. A chunk past the end of the content
+    /// is empty and marked as the last one.
+    QueryPostChunk {
+        id: PostId,
+        chunk_index: u32,
+        chunk_size: u32,
+    },
+    /// Query the contents of several posts at once, up to `max_page_size`, each with its own result
+    QueryPosts { ids: Vec<PostId> },
+    /// Check whether `account` can read a post, without revealing the whole access list
+    ///
+    /// Anyone can check a public post, which is readable by everyone, only the owner can check a private one.
+    CanRead { id: PostId, account: AccountId },
+    /// Query the first created post of `owner` visible to the sender, or of the public posts if there is no `owner`
+    ///
+    /// The smallest id wins among the posts created at the same time.
+    Oldest { owner: Option<AccountId> },
+    /// Query the last created post of `owner` visible to the sender, or of the public posts if there is no `owner`
+    ///
+    /// The greatest id wins among the posts created at the same time, like in `QueryByTitle`.
+    Newest { owner: Option<AccountId> },
+    /// List the replies to a post visible to the sender, ordered by creation time
+    QueryReplies {
+        id: PostId,
+        offset: u32,
+        limit: u32,
+    },
+    /// Query the latest root Commands starting from the `offset`-th one, oldest first, only allowed for the root
+    /// account
+    QueryAuditLog { offset: u32, limit: u32 },
+    /// Query the `blake2_256` of the content, for the clients to check their cached copy before fetching it again
+    ///
+    /// The hash is of the UTF-8 bytes of the content as returned by `QueryPost`, whether it is stored compressed or not.
+    QueryContentHash { id: PostId },
+    /// Query the SCALE encoding of a post as stored, to be kept or forwarded verbatim and read with `decode_raw_post`
+    ///
+    /// Unlike `QueryPost`, the content is left compressed if it is stored so. The bytes are also accepted by
+    /// `ImportPosts` if the sender owns the post. Like in all the Queries returning a post, the readers are left out
+    /// unless the sender is the owner.
+    QueryPostRaw { id: PostId },
+    /// Query the lines changed from the content at `from_version` to the one at `to_version`
+    ///
+    /// The versions are the indices of the previous contents listed by `QueryHistory`, and the length of that list for
+    /// the current content. They shift when the oldest contents are dropped from the bounded history. Only the first
+    /// `max_diff_lines` changes are returned.
+    QueryDiff {
+        id: PostId,
+        from_version: u32,
+        to_version: u32,
+    },
 }
 
 /// The Query results
 #[derive(Encode, Decode, Debug, Clone)]
 pub enum Response {
     Post(Post),
+    Stats {
+        /// The posts which can still be read, i.e. neither expired, deleted nor exhausted
+        total_posts: u32,
+        /// The posts counted against `max_posts_per_owner`, including the deleted ones not purged yet
+        owner_posts: u32,
+        max_posts_per_owner: u32,
+    },
+    ValidationResult(Result<(), String>),
+    Expired(bool),
+    PostList(Vec<PostSummary>),
+    ContentSlice(PostContent),
+    AccessList(Vec<AccountId>),
+    /// A page of the public feed, `next_cursor` is `None` on the last page
+    PublicFeed {
+        posts: Vec<PostSummary>,
+        next_cursor: Option<PostId>,
+    },
+    Version { major: u16, minor: u16 },
+    Time {
+        wall_clock: u64,
+        block_number: chain::BlockNumber,
+        block_time: u64,
+    },
+    History(Vec<(UpdateOn, PostContent)>),
+    PostOwner(AccountId),
+    PostWithDates { post: Post, dates: PostDates },
+    /// A page of the exported posts bounded by `max_export_bytes`, `next_cursor` is `None` on the last page
+    Export {
+        posts: Vec<Post>,
+        next_cursor: Option<PostId>,
+    },
+    Exists(bool),
+    ShareToken(Option<ShareToken>),
+    Chunk { data: Vec<u8>, is_last: bool },
+    /// The results of `QueryPosts` in the order of the ids
+    Posts(Vec<Result<Post, Error>>),
+    CanRead(bool),
+    PostSummary(PostSummary),
+    AuditLog(Vec<AuditEntry>),
+    ContentHash([u8; 32]),
+    RawPost(Vec<u8>),
+    /// The changed lines in order, `truncated` if there were more than `max_diff_lines`
+    Diff { lines: Vec<DiffLine>, truncated: bool },
 }
 
-#[derive(Encode, Decode, Debug)]
+/// A line changed between two contents, numbered from 0 in the content it belongs to
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+pub enum DiffLine {
+    /// A line of the `to` content missing from the `from` one
+    Added { line: u32, text: String },
+    /// A line of the `from` content missing from the `to` one
+    Removed { line: u32, text: String },
+}
+
+/// The ISO-8601 representations of the timestamps of a post
+///
+/// They are only formatted for the Query responses, the contract state keeps the Unix timestamps.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct PostDates {
+    created_on: String,
+    updated_on: String,
+    expires_at: Option<String>,
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
 pub enum Error {
     OriginUnavailable,
     NotAuthorized,
     NotFound,
+    BadSignature,
+    SignatureExpired,
+    InvalidUtcOffset,
+    DateOutOfRange,
+    InvalidRange,
+    InvalidChunkSize,
+    BatchTooLarge,
+    /// The post is archived, its owner has to restore it with `UnarchivePost` before it can be read
+    Archived,
+    /// The version is past the current content of the post
+    InvalidVersion,
 }
 
-impl Pastebin {
-    pub fn new() -> Self {
-        Pastebin {
-            post_by_id: HashMap::new(),
+impl Error {
+    /// A stable HTTP-like status code of the error, for the clients which can't match the variants
+    pub fn code(&self) -> u16 {
+        match self {
+            Error::OriginUnavailable => 401,
+            Error::NotAuthorized => 403,
+            Error::NotFound => 404,
+            Error::BadSignature => 401,
+            Error::SignatureExpired => 401,
+            Error::InvalidUtcOffset => 400,
+            Error::DateOutOfRange => 400,
+            Error::InvalidRange => 400,
+            Error::InvalidChunkSize => 400,
+            Error::BatchTooLarge => 413,
+            Error::Archived => 410,
+            Error::InvalidVersion => 400,
         }
     }
 }
 
-// Alice is the pre-defined root account in dev mode
-const ALICE: &str = "d43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d";
+/// Reject a public text containing one of the lowercase `blocked_words`, the private posts are not moderated
+fn check_blocked(blocked_words: &BTreeSet<String>, is_private: bool, text: &str) -> TransactionResult {
+    if is_private || blocked_words.is_empty() {
+        return Ok(());
+    }
+    let text = text.to_lowercase();
+    if blocked_words.iter().any(|word| text.contains(word.as_str())) {
+        return Err(TransactionError::Blocked);
+    }
+    Ok(())
+}
 
-impl contracts::NativeContract for Pastebin {
-    type Cmd = Command;
-    type QReq = Request;
-    type QResp = Result<Response, Error>;
+/// Check that the content of a post not encrypted decodes with its declared encoding
+fn check_content_encoding(
+    encoding: ContentEncoding,
+    encryption: Option<&EncryptionMeta>,
+    content: &str,
+) -> TransactionResult {
+    let well_formed = match (encoding, encryption) {
+        // the encoding applies to the plaintext, which the contract can't read
+        (_, Some(_)) | (ContentEncoding::Utf8, _) => true,
+        (ContentEncoding::Base64, None) => base64::decode(content).is_ok(),
+        (ContentEncoding::Hex, None) => hex::decode(content).is_ok(),
+    };
+    if !well_formed {
+        return Err(TransactionError::BadInput);
+    }
+    Ok(())
+}
 
-    /// Return the contract id which uniquely identifies the contract
-    fn id(&self) -> contracts::ContractId32 {
-        contracts::PASTEBIN
+/// The archived form of a post, its deflate-compressed SCALE encoding
+///
+/// The encoding carries the layout version, so a post archived by an older contract is upgraded when restored.
+fn archive_blob(post: &Post) -> Vec<u8> {
+    miniz_oxide::deflate::compress_to_vec(&post.encode(), 6)
+}
+
+fn unarchive_blob(blob: &[u8]) -> Result<Post, CodecError> {
+    let encoded = miniz_oxide::inflate::decompress_to_vec(blob).or(Err("Invalid archive compression"))?;
+    Post::decode(&mut &encoded[..])
+}
+
+/// The readers of a post of `owner` without the duplicates and the owner, who can always read it, in the given order
+fn canonical_readers(owner: &AccountId, readers: impl IntoIterator<Item = AccountId>) -> Vec<AccountId> {
+    let mut canonical: Vec<AccountId> = vec![];
+    for reader in readers {
+        if &reader != owner && !canonical.contains(&reader) {
+            canonical.push(reader);
+        }
     }
+    canonical
+}
 
-    /// Handle the Commands from transactions on the blockchain. This method doesn't respond.
-    ///
-    /// # Arguments
-    ///
-    /// * `context` - The current block info with the necessary egress channel
-    /// * `origin` - The sender of the Command, can be Pallet, pRuntime, Contract, Account or even entities from other chain
-    /// * `cmd` - The on-chain Command to process
-    fn handle_command(
-        &mut self,
-        context: &mut NativeContext,
-        origin: MessageOrigin,
-        cmd: Command,
-    ) -> TransactionResult {
-        info!("Command received: {:?}", &cmd);
+/// The canonical readers of a new post
+fn readers_of(new_post: &NewPost) -> Vec<AccountId> {
+    let owner = AccountId::from(*new_post.owner.as_fixed_bytes());
+    let readers = new_post.readable_by.iter().map(|reader| AccountId::from(*reader.as_fixed_bytes()));
+    canonical_readers(&owner, readers)
+}
 
-        // we want to limit the sender who can use the Commands to the pre-define root account
-        let sender = match &origin {
-            MessageOrigin::AccountId(account) => AccountId::from(*account.as_fixed_bytes()),
-            _ => return Err(TransactionError::BadOrigin),
-        };
-        let alice = contracts::account_id_from_hex(ALICE)
-            .expect("should not failed with valid address; qed.");
-        match cmd {
-            Command::CreatePost {
-                id,
-                owner,
-                is_private,
-                readable_by,
-                content,
-                title,
-            } => {
-                log::info!("id: {:?}, owner: {:?}, is_private: {:?}, readable_by: {:?}, content: {:?}, title: {:?} ", id, owner, is_private, readable_by, content, title);
-                if self.post_by_id.contains_key(&id) {
-                    return Err(TransactionError::IdExists);
-                }
+/// Decode a post returned by `QueryPostRaw`, upgrading it to the current layout if it is encoded by an older contract
+///
+/// The content of the decoded post is uncompressed, and rejected if larger than `max_content_bytes`.
+pub fn decode_raw_post(raw: &[u8], max_content_bytes: usize) -> Result<Post, CodecError> {
+    let mut post = Post::decode_all(raw)?;
+    let content = post
+        .untrusted_content(max_content_bytes)
+        .ok_or("Malformed or too large post content")?;
+    post.content = content.into_bytes();
+    post.compressed = false;
+    Ok(post)
+}
 
-                // let mut mut_readable_by = Vec::new();
+/// The message to be signed for `QueryPostSigned`, the post id followed by the little-endian Unix timestamp in seconds
+pub fn signed_read_payload(id: &str, timestamp: u64) -> Vec<u8> {
+    let mut payload = id.as_bytes().to_vec();
+    payload.extend_from_slice(&timestamp.to_le_bytes());
+    payload
+}
+
+/// Format a Unix timestamp in seconds as an ISO-8601 (RFC 3339) date at the given UTC offset
+fn format_timestamp(timestamp: u64, utc_offset_minutes: i32) -> Result<String, Error> {
+    let offset = utc_offset_minutes
+        .checked_mul(60)
+        .and_then(FixedOffset::east_opt)
+        .ok_or(Error::InvalidUtcOffset)?;
+    let date = i64::try_from(timestamp)
+        .ok()
+        .and_then(|timestamp| offset.timestamp_opt(timestamp, 0).single())
+        .ok_or(Error::DateOutOfRange)?;
+    Ok(date.to_rfc3339())
+}
 
-                // for u in readable_by {
-                //     mut_readable_by.push(H256::from(*u.as_fixed_bytes()))
-                // }
+/// The bound of the table aligning the changed lines in `line_diff`, about 4 MB
+const MAX_DIFF_CELLS: usize = 1 << 20;
 
-                // let data = &mut_readable_by;
-                // let immut_readable_by = &*data;
+/// The lines removed from `from` and added in `to`, in order and up to `max_lines`, and whether there were more
+///
+/// The lines are aligned along their longest common subsequence, after skipping the common first and last lines. If
+/// the remaining lines are too many to align within `MAX_DIFF_CELLS`, they are all reported as replaced.
+fn line_diff(from: &str, to: &str, max_lines: usize) -> (Vec<DiffLine>, bool) {
+    let from: Vec<&str> = from.lines().collect();
+    let to: Vec<&str> = to.lines().collect();
+    let prefix = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let suffix = from[prefix..]
+        .iter()
+        .rev()
+        .zip(to[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old = &from[prefix..from.len() - suffix];
+    let new = &to[prefix..to.len() - suffix];
+    let removed = |i: usize| DiffLine::Removed {
+        line: (prefix + i) as u32,
+        text: old[i].to_string(),
+    };
+    let added = |j: usize| DiffLine::Added {
+        line: (prefix + j) as u32,
+        text: new[j].to_string(),
+    };
 
-                let post = Post {
-                    id: id.clone(),
-                    owner: AccountId::from(*owner.as_fixed_bytes()),
-                    is_private: is_private,
-                    readable_by: AccountId::from(*readable_by.as_fixed_bytes()),
-                    // readable_by: immut_readable_by.to_vec(), //AccountId::from(*readable_by.as_fixed_bytes()),
-                    content: content,
-                    created_on: now(),
-                    title: title,
+    let mut changes = Vec::new();
+    if (old.len() + 1).saturating_mul(new.len() + 1) > MAX_DIFF_CELLS {
+        changes.extend((0..old.len()).map(removed));
+        changes.extend((0..new.len()).map(added));
+    } else {
+        // `common[i * width + j]` is the length of the longest common subsequence of `old[i..]` and `new[j..]`
+        let width = new.len() + 1;
+        let mut common = vec![0u32; (old.len() + 1) * width];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                common[i * width + j] = if old[i] == new[j] {
+                    common[(i + 1) * width + j + 1] + 1
+                } else {
+                    common[(i + 1) * width + j].max(common[i * width + j + 1])
                 };
-                log::info!("Post: {:?}", post);
-                self.post_by_id.insert(id.clone(), post);
-                match self.post_by_id.get(&id) {
-                    Some(post) => {
-                        info!("Query received - Create - Post: {:?}", post);
-                    },
-                    None => {
-                        info!("Query received - Create - Post notfound",);
-                    }
-                }
-                Ok(())
             }
         }
+        let (mut i, mut j) = (0, 0);
+        while i < old.len() || j < new.len() {
+            if i < old.len() && j < new.len() && old[i] == new[j] {
+                i += 1;
+                j += 1;
+            } else if j == new.len() || (i < old.len() && common[(i + 1) * width + j] >= common[i * width + j + 1]) {
+                changes.push(removed(i));
+                i += 1;
+            } else {
+                changes.push(added(j));
+                j += 1;
+            }
+        }
+    }
+    let truncated = changes.len() > max_lines;
+    changes.truncate(max_lines);
+    (changes, truncated)
+}
+
+/// The version of this contract, the minor one is bumped when Requests or Commands are added, the major one when
+/// they are changed incompatibly
+const VERSION_MAJOR: u16 = 5;
+const VERSION_MINOR: u16 = 0;
+
+// Alice is the pre-defined root account in dev mode
+const ALICE: &str = "d43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d";
+
+impl Pastebin {
+    /// Create the contract with the default config, for dev mode and tests
+    pub fn new_dev() -> Self {
+        Self::new(Default::default())
+    }
+
+    pub fn new(config: PastebinConfig) -> Self {
+        Pastebin {
+            root: config.root.clone(),
+            max_posts_per_owner: config.max_posts_per_owner,
+            max_readers: config.max_readers,
+            default_ttl: config.default_ttl,
+            max_ttl: config.max_ttl,
+            reject_longer_ttl: config.reject_longer_ttl,
+            min_balance_to_post: config.min_balance_to_post,
+            config,
+            post_by_id: BTreeMap::new(),
+            post_count_by_owner: HashMap::new(),
+            trash: BTreeSet::new(),
+            share_tokens: BTreeMap::new(),
+            expiring: BTreeSet::new(),
+            blocked_words: BTreeSet::new(),
+            paused: false,
+            audit_log: vec![],
+            archived: BTreeMap::new(),
+            last_block: (0, 0),
+        }
     }
 
-    /// Handle a direct Query and respond to it. It shouldn't modify the contract state.
-    ///
-    /// # Arguments
+    /// Whether a post, live, deleted or archived, holds the id
+    fn is_id_taken(&self, id: &PostId) -> bool {
+        self.post_by_id.contains_key(id) || self.archived.contains_key(id)
+    }
+
+    /// Check that the sender of a Command holding `free_balance` on chain may post
     ///
-    /// * `origin` - For off-chain Query, the sender can only be AccountId
-    /// * `req` — Off-chain Query to handle
-    fn handle_query(
-        &mut self,
-        origin: Option<&chain::AccountId>,
-        req: Request,
-    ) -> Result<Response, Error> {
-        info!("Query received: {:?}", &req);
-        match req {
-            Request::QueryPost { id } => {
-                if !self.post_by_id.contains_key(&id) {
-                    return Err(Error::NotFound);
-                }
-                let sender = origin.ok_or(Error::OriginUnavailable)?;
+    /// The balance is read from the chain state of the block the Command is handled in, which all the replicas share.
+    fn check_posting_policy(&self, free_balance: chain::Balance) -> TransactionResult {
+        match self.min_balance_to_post {
+            Some(min_balance) if free_balance < min_balance => Err(TransactionError::Unauthorized),
+            _ => Ok(()),
+        }
+    }
 
-                match self.post_by_id.get(&id) {
-                    Some(post) => {
-                        info!("Query received - Read - Post: {:?}", post.clone());
-
-                        if !post.is_private || sender == &post.owner || sender == &post.readable_by {
-                            return Ok(Response::Post(post.clone()))
-                        }
-                        // for u in &post.readable_by {
-                        //     if sender.to_string() == u.to_string() {
-                        //         return Ok(Response::Post(post.clone()))
-                        //     }
-                        // }
-                        return Err(Error::NotAuthorized);
-                    },
-                    None => return Err(Error::NotFound)
-                }
+    fn post_count_of(&self, owner: &AccountId) -> u32 {
+        self.post_count_by_owner.get(owner).cloned().unwrap_or(0)
+    }
+
+    /// The id, size and quota checks of a new post, part of `validate_new_post`
+    fn validate_create(
+        &self,
+        id: &PostId,
+        owner: &AccountId,
+        content: &PostContent,
+    ) -> TransactionResult {
+        self.validate_id(id)?;
+        if self.is_id_taken(id) {
+            return Err(TransactionError::IdExists);
+        }
+        if content.len() > self.config.max_content_bytes {
+            return Err(self.content_too_large());
+        }
+        if self.post_count_of(owner) >= self.max_posts_per_owner {
+            return Err(TransactionError::QuotaExceeded);
+        }
+        Ok(())
+    }
+
+    /// The error of a content beyond `max_content_bytes`, telling the clients the limit
+    fn content_too_large(&self) -> TransactionError {
+        TransactionError::ContentTooLarge(self.config.max_content_bytes as u32)
+    }
+
+    /// Whether the post already exists exactly as `new_post` would create it
+    ///
+    /// A `CreatePost` of such a post is a retry, so it succeeds without changing anything instead of failing with
+    /// `IdExists`. Any difference, e.g. in the privacy or the readers, still fails, rather than reporting a success
+    /// while keeping the stored settings.
+    fn is_created(&self, new_post: &NewPost) -> bool {
+        let owner = AccountId::from(*new_post.owner.as_fixed_bytes());
+        match self.post_by_id.get(&new_post.id) {
+            Some(post) if !post.is_deleted() => {
+                post.owner == owner
+                    && post.is_private == new_post.is_private
+                    && post.readable_by == readers_of(new_post)
+                    && post.title == new_post.title
+                    && post.expires_at == self.effective_expiry(new_post.expires_at, post.created_on)
+                    && post.metadata == new_post.metadata
+                    && post.encryption == new_post.encryption
+                    && post.parent_id == new_post.parent_id
+                    && post.content_encoding == new_post.content_encoding
+                    && post.content_len as usize == new_post.content.len()
+                    && post.content() == new_post.content
             }
+            _ => false,
+        }
+    }
+
+    /// Reject a new post id not allowed by the `id_policy`
+    fn validate_id(&self, id: &PostId) -> TransactionResult {
+        if !self.config.id_policy.allows(id) {
+            return Err(TransactionError::InvalidInput);
+        }
+        Ok(())
+    }
+
+    /// Reject the control characters in a title, which is shown on a single line by the listings
+    ///
+    /// The SCALE decoding of a `String` already rejects malformed UTF-8, so only the characters are left to check.
+    fn validate_title(&self, title: &PostTitle) -> TransactionResult {
+        if title.chars().any(char::is_control) {
+            return Err(TransactionError::InvalidInput);
+        }
+        Ok(())
+    }
+
+    /// Check the title and the content of a new post against the blocked words
+    fn validate_public_text(
+        &self,
+        is_private: bool,
+        title: &PostTitle,
+        content: &PostContent,
+    ) -> TransactionResult {
+        check_blocked(&self.blocked_words, is_private, title)?;
+        check_blocked(&self.blocked_words, is_private, content)
+    }
+
+    /// Check the bounds of a single metadata entry
+    fn validate_metadata_entry(&self, key: &str, value: &str) -> TransactionResult {
+        if key.len() > self.config.max_metadata_key_bytes
+            || value.len() > self.config.max_metadata_value_bytes
+        {
+            return Err(TransactionError::MetadataTooLarge);
+        }
+        Ok(())
+    }
+
+    fn validate_metadata(&self, metadata: &Metadata) -> TransactionResult {
+        if metadata.len() > self.config.max_metadata_entries {
+            return Err(TransactionError::MetadataTooLarge);
         }
+        for (key, value) in metadata {
+            self.validate_metadata_entry(key, value)?;
+        }
+        Ok(())
+    }
+
+    fn validate_readers<T>(&self, readable_by: &[T]) -> TransactionResult {
+        if readable_by.len() > self.max_readers {
+            return Err(TransactionError::TooManyReaders);
+        }
+        Ok(())
+    }
+
+    /// Check that the content of an encrypted post is a well-formed ciphertext
+    ///
+    /// The contract has no key, it can only check the shape of the ciphertext, not that it decrypts.
+    fn validate_encryption(
+        &self,
+        encryption: Option<&EncryptionMeta>,
+        content: &PostContent,
+    ) -> TransactionResult {
+        let encryption = match encryption {
+            Some(encryption) => encryption,
+            None => return Ok(()),
+        };
+        if encryption.nonce.len() != encryption.algorithm.nonce_len() {
+            return Err(TransactionError::InvalidEncryption);
+        }
+        let ciphertext = base64::decode(content).or(Err(TransactionError::InvalidEncryption))?;
+        if ciphertext.len() < encryption.algorithm.tag_len() {
+            return Err(TransactionError::InvalidEncryption);
+        }
+        Ok(())
+    }
+
+    /// The live posts of `owner`, or the public posts if there is no `owner`, which the `sender` can read
+    fn scoped_posts<'a>(
+        &'a self,
+        owner: Option<&'a AccountId>,
+        sender: Option<&'a AccountId>,
+        now: u64,
+    ) -> impl Iterator<Item = &'a Post> + 'a {
+        self.post_by_id
+            .values()
+            .filter(move |post| owner.map_or(!post.is_private, |owner| &post.owner == owner))
+            .filter(move |post| !post.is_private || sender.map_or(false, |sender| post.is_readable_by(sender)))
+            .filter(move |post| post.is_live(now))
+    }
+
+    /// Look up a post for the `reader`, hiding expired posts and checking the read permission
+    fn read_post(&self, id: &PostId, reader: &AccountId) -> Result<&Post, Error> {
+        if self.archived.contains_key(id) {
+            return Err(Error::Archived);
+        }
+        let post = self.post_by_id.get(id).ok_or(Error::NotFound)?;
+        info!("Query received - Read - Post: {:?}", post);
+        if !post.is_live(now()) {
+            return Err(Error::NotFound);
+        }
+        if !post.is_readable_by(reader) {
+            return Err(Error::NotAuthorized);
+        }
+        Ok(post)
+    }
+
+    /// The expiry of a post created at `created_on`
+    ///
+    /// An explicit expiry overrides the default one, and both are capped by the maximum lifetime.
+    fn effective_expiry(&self, expires_at: Option<ExpireAt>, created_on: CreateOn) -> Option<ExpireAt> {
+        let expires_at = expires_at.or_else(|| self.default_ttl.map(|ttl| created_on.saturating_add(ttl)));
+        match self.max_ttl.map(|ttl| created_on.saturating_add(ttl)) {
+            Some(latest) => Some(expires_at.map_or(latest, |expires_at| expires_at.min(latest))),
+            None => expires_at,
+        }
+    }
+
+    /// Check that `owner` can reply to `parent_id` with the post `id`
+    ///
+    /// The parent must be a live post readable by `owner`, and the reply must not end up among its own ancestors. The
+    /// walk up the thread is bounded by the number of posts, so that a cycle left by an import can't loop forever.
+    fn validate_parent(
+        &self,
+        id: &PostId,
+        owner: &AccountId,
+        parent_id: Option<&PostId>,
+        now: u64,
+    ) -> TransactionResult {
+        let parent_id = match parent_id {
+            Some(parent_id) => parent_id,
+            None => return Ok(()),
+        };
+        let parent = self
+            .post_by_id
+            .get(parent_id)
+            .filter(|post| !post.is_deleted() && !post.is_expired(now))
+            .ok_or(TransactionError::NotFound)?;
+        if !parent.is_readable_by(owner) {
+            return Err(TransactionError::Unauthorized);
+        }
+        let mut ancestor = Some(parent_id);
+        for _ in 0..=self.post_by_id.len() {
+            match ancestor {
+                Some(ancestor_id) if ancestor_id == id => return Err(TransactionError::BadInput),
+                Some(ancestor_id) => {
+                    ancestor = self.post_by_id.get(ancestor_id).and_then(|post| post.parent_id.as_ref())
+                }
+                None => return Ok(()),
+            }
+        }
+        Err(TransactionError::BadInput)
+    }
+
+    /// Reject an explicit expiry beyond the maximum lifetime, if the longer ones are not to be shortened
+    fn validate_expiry(&self, expires_at: Option<ExpireAt>, created_on: CreateOn) -> TransactionResult {
+        match (expires_at, self.max_ttl) {
+            (Some(expires_at), Some(ttl))
+                if self.reject_longer_ttl && expires_at > created_on.saturating_add(ttl) =>
+            {
+                Err(TransactionError::TtlTooLong)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Create a post which has already passed `validate_create`
+    fn insert_post(&mut self, new_post: NewPost, created_on: CreateOn) {
+        let owner = AccountId::from(*new_post.owner.as_fixed_bytes());
+        let mut post = Post {
+            version: POST_VERSION,
+            id: new_post.id.clone(),
+            owner: owner.clone(),
+            is_private: new_post.is_private,
+            readable_by: readers_of(&new_post),
+            created_on,
+            title: new_post.title,
+            updated_on: created_on,
+            expires_at: self.effective_expiry(new_post.expires_at, created_on),
+            metadata: new_post.metadata,
+            encryption: new_post.encryption,
+            parent_id: new_post.parent_id,
+            content_encoding: new_post.content_encoding,
+            ..Default::default()
+        };
+        post.set_content(new_post.content);
+        log::info!("Post: {:?}", post);
+        self.post_by_id.insert(new_post.id, post);
+        *self.post_count_by_owner.entry(owner).or_default() += 1;
+    }
+
+    /// Import the posts of `owner` exported by `ExportPosts`, keeping their timestamps and history
+    ///
+    /// Like `create_posts`, the whole batch is validated before importing anything. The posts are checked as new
+    /// ones, since an export can be edited before being imported.
+    ///
+    /// The maximum lifetime applies from the time of the import, and the longer ones are always shortened since the
+    /// client has no way to fix them.
+    ///
+    /// The `parent_id` of the posts is kept unchecked, since a parent may come later in the same or the next batch.
+    fn import_posts(
+        &mut self,
+        owner: &AccountId,
+        posts: Vec<Vec<u8>>,
+        skip_existing: bool,
+        imported_on: u64,
+    ) -> TransactionResult {
+        if posts.len() > self.config.max_batch_size {
+            return Err(TransactionError::BatchTooLarge);
+        }
+        let mut batch_ids = HashSet::new();
+        let mut imported = vec![];
+        for (index, encoded) in posts.iter().enumerate() {
+            let result = self.check_import(owner, encoded).and_then(|(post, content)| {
+                if self.is_id_taken(&post.id) || !batch_ids.insert(post.id.clone()) {
+                    return if skip_existing { Ok(None) } else { Err(TransactionError::IdExists) };
+                }
+                if self.post_count_of(owner) + imported.len() as u32 >= self.max_posts_per_owner {
+                    return Err(TransactionError::QuotaExceeded);
+                }
+                Ok(Some((post, content)))
+            });
+            match result {
+                Ok(Some(post)) => imported.push(post),
+                Ok(None) => info!("Batch item {} skipped, the id is taken", index),
+                Err(err) => {
+                    info!("Batch item {} rejected: {:?}", index, err);
+                    return Err(TransactionError::BatchItemRejected(index as u32));
+                }
+            }
+        }
+        for (mut post, content) in imported {
+            post.set_content(content);
+            post.deleted_at = None;
+            post.pinned = false;
+            if let Some(ttl) = self.max_ttl {
+                let latest = imported_on.saturating_add(ttl);
+                post.expires_at = Some(post.expires_at.map_or(latest, |expires_at| expires_at.min(latest)));
+            }
+            if let (ExpiryAction::Delete, Some(expires_at)) = (post.on_expiry, post.expires_at) {
+                self.expiring.insert((expires_at, post.id.clone()));
+            }
+            self.post_by_id.insert(post.id.clone(), post);
+            *self.post_count_by_owner.entry(owner.clone()).or_default() += 1;
+        }
+        Ok(())
+    }
+
+    /// Decode an exported post and check it like a new one, returning it with its uncompressed content
+    fn check_import(&self, owner: &AccountId, encoded: &[u8]) -> Result<(Post, PostContent), TransactionError> {
+        let mut post = Post::decode(&mut &encoded[..]).or(Err(TransactionError::BadInput))?;
+        if &post.owner != owner {
+            return Err(TransactionError::Unauthorized);
+        }
+        post.readable_by = canonical_readers(owner, post.readable_by);
+        let content = post
+            .untrusted_content(self.config.max_content_bytes)
+            .ok_or_else(|| self.content_too_large())?;
+        self.validate_id(&post.id)?;
+        self.validate_title(&post.title)?;
+        self.validate_public_text(post.is_private, &post.title, &content)?;
+        self.validate_metadata(&post.metadata)?;
+        self.validate_readers(&post.readable_by)?;
+        self.validate_encryption(post.encryption.as_ref(), &content)?;
+        check_content_encoding(post.content_encoding, post.encryption.as_ref(), &content)?;
+        let history_bytes: usize = post.history.iter().map(|(_, old)| old.len()).sum();
+        if post.history.len() > self.config.max_history_entries
+            || history_bytes > self.config.max_history_bytes
+        {
+            return Err(TransactionError::ContentTooLarge(self.config.max_history_bytes as u32));
+        }
+        Ok((post, content))
+    }
+
+    /// Move a post to the trash, or take it back if `deleted_at` is `None`
+    fn set_deleted(
+        &mut self,
+        sender: &AccountId,
+        id: &PostId,
+        deleted_at: Option<u64>,
+    ) -> TransactionResult {
+        let post = self.post_by_id.get_mut(id).ok_or(TransactionError::NotFound)?;
+        if sender != &post.owner {
+            return Err(TransactionError::Unauthorized);
+        }
+        match (post.deleted_at, deleted_at) {
+            (None, Some(deleted_at)) => {
+                self.trash.insert((deleted_at, id.clone()));
+                // a restored post comes back unpinned, so that it never pushes its owner over `max_pinned_posts`
+                post.pinned = false;
+            }
+            (Some(deleted_at), None) => {
+                self.trash.remove(&(deleted_at, id.clone()));
+            }
+            // deleting a deleted post or restoring a live one finds nothing to act on
+            _ => return Err(TransactionError::NotFound),
+        }
+        post.deleted_at = deleted_at;
+        Ok(())
+    }
+
+    /// Purge the posts which have been in the trash for `trash_retention_secs`
+    ///
+    /// It is driven by the block time, so all the replicas purge the same posts at the same block.
+    fn purge_trash(&mut self, now: u64) {
+        while let Some((deleted_at, id)) = self.trash.iter().next().cloned() {
+            if deleted_at.saturating_add(self.config.trash_retention_secs) > now {
+                break;
+            }
+            self.trash.remove(&(deleted_at, id.clone()));
+            // the post may have been cleared and its id reused since it was deleted
+            if !matches!(self.post_by_id.get(&id), Some(post) if post.deleted_at == Some(deleted_at)) {
+                continue;
+            }
+            self.remove_post(&id);
+            info!("Post {} purged from the trash", id);
+        }
+    }
+
+    /// Remove the expired posts set to `ExpiryAction::Delete`
+    ///
+    /// Like `purge_trash`, it is driven by the block time. The other expired posts are only hidden by the Queries.
+    fn sweep_expired(&mut self, now: u64) {
+        while let Some((expires_at, id)) = self.expiring.iter().next().cloned() {
+            if expires_at > now {
+                break;
+            }
+            self.expiring.remove(&(expires_at, id.clone()));
+            // the post may have been renamed, set back to `Hide`, or cleared and its id reused since it was queued
+            if !matches!(
+                self.post_by_id.get(&id),
+                Some(post) if post.on_expiry == ExpiryAction::Delete && post.expires_at == Some(expires_at)
+            ) {
+                continue;
+            }
+            self.remove_post(&id);
+            info!("Post {} removed on expiry", id);
+        }
+    }
+
+    /// Remove the expired posts at once, all of them for the root account and its own ones for any other `sender`,
+    /// and return how many are removed
+    ///
+    /// Unlike `sweep_expired`, the posts set to `ExpiryAction::Hide` and the deleted ones are removed as well.
+    fn purge_expired(&mut self, sender: &AccountId, now: u64) -> u32 {
+        let is_root = sender == &self.root;
+        let expired: Vec<PostId> = self
+            .post_by_id
+            .values()
+            .filter(|post| post.is_expired(now) && (is_root || &post.owner == sender))
+            .map(|post| post.id.clone())
+            .collect();
+        for id in expired.iter() {
+            self.remove_post(id);
+        }
+        expired.len() as u32
+    }
+
+    /// Remove a post with its share token from the storage
+    fn remove_post(&mut self, id: &PostId) {
+        if let Some(post) = self.post_by_id.remove(id) {
+            if let Some(token) = self.share_token_of(id) {
+                self.share_tokens.remove(&token);
+            }
+            if let Some(count) = self.post_count_by_owner.get_mut(&post.owner) {
+                *count = count.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Set what becomes of a post once it expires, queueing it for `sweep_expired` if it is to be removed
+    fn set_expiry_action(
+        &mut self,
+        sender: &AccountId,
+        id: &PostId,
+        action: ExpiryAction,
+    ) -> TransactionResult {
+        let post = self
+            .post_by_id
+            .get_mut(id)
+            .filter(|post| !post.is_deleted())
+            .ok_or(TransactionError::NotFound)?;
+        if sender != &post.owner {
+            return Err(TransactionError::Unauthorized);
+        }
+        post.on_expiry = action;
+        if let Some(expires_at) = post.expires_at {
+            match action {
+                ExpiryAction::Delete => self.expiring.insert((expires_at, id.clone())),
+                ExpiryAction::Hide => self.expiring.remove(&(expires_at, id.clone())),
+            };
+        }
+        Ok(())
+    }
+
+    /// Move a post to an unused id
+    ///
+    /// Everything is checked before touching the map, so that the post is either fully moved or left as it is.
+    fn rename_post(&mut self, sender: &AccountId, old_id: &PostId, new_id: PostId) -> TransactionResult {
+        let post = self
+            .post_by_id
+            .get(old_id)
+            .filter(|post| !post.is_deleted())
+            .ok_or(TransactionError::NotFound)?;
+        if sender != &post.owner {
+            return Err(TransactionError::Unauthorized);
+        }
+        self.validate_id(&new_id)?;
+        if self.is_id_taken(&new_id) {
+            return Err(TransactionError::IdExists);
+        }
+        let mut post = self
+            .post_by_id
+            .remove(old_id)
+            .expect("The post is checked above");
+        if let Some(token) = self.share_token_of(old_id) {
+            self.share_tokens.insert(token, new_id.clone());
+        }
+        if let Some(expires_at) = post.expires_at {
+            if self.expiring.remove(&(expires_at, old_id.clone())) {
+                self.expiring.insert((expires_at, new_id.clone()));
+            }
+        }
+        for reply in self.post_by_id.values_mut() {
+            if reply.parent_id.as_ref() == Some(old_id) {
+                reply.parent_id = Some(new_id.clone());
+            }
+        }
+        post.id = new_id.clone();
+        self.post_by_id.insert(new_id, post);
+        Ok(())
+    }
+
+    /// Copy a post readable by `sender` to an unused id, as a new post owned by `sender`
+    ///
+    /// The copy is checked like a new post of `sender`, the source is only read.
+    fn clone_post(
+        &mut self,
+        sender: &AccountId,
+        source_id: &PostId,
+        new_id: PostId,
+        created_on: CreateOn,
+    ) -> TransactionResult {
+        let source = self
+            .post_by_id
+            .get(source_id)
+            .filter(|post| post.is_live(created_on))
+            .ok_or(TransactionError::NotFound)?;
+        if !source.is_readable_by(sender) {
+            return Err(TransactionError::Unauthorized);
+        }
+        let content = source.content();
+        self.validate_create(&new_id, sender, &content)?;
+        self.validate_public_text(source.is_private, &source.title, &content)?;
+        let mut post = Post {
+            version: POST_VERSION,
+            id: new_id.clone(),
+            owner: sender.clone(),
+            is_private: source.is_private,
+            created_on,
+            title: source.title.clone(),
+            updated_on: created_on,
+            expires_at: self.effective_expiry(None, created_on),
+            metadata: source.metadata.clone(),
+            encryption: source.encryption.clone(),
+            content_encoding: source.content_encoding,
+            ..Default::default()
+        };
+        post.set_content(content);
+        log::info!("Post {} cloned: {:?}", source_id, post);
+        self.post_by_id.insert(new_id, post);
+        *self.post_count_by_owner.entry(sender.clone()).or_default() += 1;
+        Ok(())
+    }
+
+    /// Add `account` to the readers of the posts among `ids` owned by `sender`, and return how many it is added to
+    ///
+    /// The posts not found, owned by someone else, public, already readable by `account` or holding `max_readers`
+    /// readers are skipped, so that a list gathered from several owners can be granted as it is. A public post is
+    /// readable by everyone, a reader added to it would grant nothing.
+    fn grant_access_bulk(&mut self, sender: &AccountId, ids: &[PostId], account: &AccountId) -> Result<u32, TransactionError> {
+        if ids.len() > self.config.max_batch_size {
+            return Err(TransactionError::BatchTooLarge);
+        }
+        let max_readers = self.max_readers;
+        let mut granted = 0;
+        for id in ids {
+            let post = match self.post_by_id.get_mut(id) {
+                Some(post) if &post.owner == sender && post.is_private && !post.is_deleted() => post,
+                _ => continue,
+            };
+            if account == &post.owner || post.readable_by.contains(account) || post.readable_by.len() >= max_readers {
+                continue;
+            }
+            post.readable_by.push(account.clone());
+            granted += 1;
+        }
+        Ok(granted)
+    }
+
+    /// Count a read of a post by `sender`, unless it is the owner
+    fn record_view(&mut self, sender: &AccountId, id: &PostId, now: u64) -> TransactionResult {
+        let post = self
+            .post_by_id
+            .get_mut(id)
+            .filter(|post| post.is_live(now))
+            .ok_or(TransactionError::NotFound)?;
+        if !post.is_readable_by(sender) {
+            return Err(TransactionError::Unauthorized);
+        }
+        if sender != &post.owner {
+            post.read_count += 1;
+        }
+        Ok(())
+    }
+
+    /// Limit the number of recorded reads of a post, the reads already recorded count towards the new limit
+    fn set_max_reads(&mut self, sender: &AccountId, id: &PostId, max_reads: Option<u64>) -> TransactionResult {
+        let post = self
+            .post_by_id
+            .get_mut(id)
+            .filter(|post| !post.is_deleted())
+            .ok_or(TransactionError::NotFound)?;
+        if sender != &post.owner {
+            return Err(TransactionError::Unauthorized);
+        }
+        if max_reads == Some(0) {
+            return Err(TransactionError::BadInput);
+        }
+        post.max_reads = max_reads;
+        Ok(())
+    }
+
+    /// Check a read of a post reported by `sender`, and return whether its owner asked to be notified of it
+    ///
+    /// Only the cooperating clients report their reads, see `Command::RecordAccess`. The owner's own reads are never
+    /// notified.
+    fn record_access(&self, sender: &AccountId, id: &PostId, now: u64) -> Result<bool, TransactionError> {
+        let post = self
+            .post_by_id
+            .get(id)
+            .filter(|post| post.is_live(now))
+            .ok_or(TransactionError::NotFound)?;
+        if !post.is_readable_by(sender) {
+            return Err(TransactionError::Unauthorized);
+        }
+        Ok(post.notify_reads && sender != &post.owner)
+    }
+
+    /// Choose whether the reads of a post reported by `RecordAccess` are notified to its owner
+    fn set_read_notice(&mut self, sender: &AccountId, id: &PostId, enabled: bool) -> TransactionResult {
+        let post = self
+            .post_by_id
+            .get_mut(id)
+            .filter(|post| !post.is_deleted())
+            .ok_or(TransactionError::NotFound)?;
+        if sender != &post.owner {
+            return Err(TransactionError::Unauthorized);
+        }
+        post.notify_reads = enabled;
+        Ok(())
+    }
+
+    /// Pin or unpin a post of `sender`, at most `max_pinned_posts` of the live posts of an owner are pinned
+    fn set_pinned(&mut self, sender: &AccountId, id: &PostId, pinned: bool) -> TransactionResult {
+        let post = self
+            .post_by_id
+            .get(id)
+            .filter(|post| !post.is_deleted())
+            .ok_or(TransactionError::NotFound)?;
+        if sender != &post.owner {
+            return Err(TransactionError::Unauthorized);
+        }
+        if pinned && !post.pinned {
+            let pinned_count = self
+                .post_by_id
+                .values()
+                .filter(|post| &post.owner == sender && post.pinned && !post.is_deleted())
+                .count();
+            if pinned_count >= self.config.max_pinned_posts {
+                return Err(TransactionError::QuotaExceeded);
+            }
+        }
+        if let Some(post) = self.post_by_id.get_mut(id) {
+            post.pinned = pinned;
+        }
+        Ok(())
+    }
+
+    fn share_token_of(&self, id: &PostId) -> Option<ShareToken> {
+        self.share_tokens
+            .iter()
+            .find(|(_, post_id)| *post_id == id)
+            .map(|(token, _)| *token)
+    }
+
+    /// The seed of a new share token of a post
+    ///
+    /// The block entropy is public, so the seed includes the content of the private post, which is only known to its
+    /// readers, and the previous token, so that a new token differs from it even within the same block.
+    fn share_token_seed(&self, id: &PostId) -> Vec<u8> {
+        let content = self.post_by_id.get(id).map(|post| &post.content);
+        (b"share_token", id, content, self.share_token_of(id)).encode()
+    }
+
+    /// Move a live post of `sender` to the archive
+    ///
+    /// The share token of the post is revoked, and it is left out of `sweep_expired` until restored.
+    fn archive_post(&mut self, sender: &AccountId, id: &PostId) -> TransactionResult {
+        let post = self
+            .post_by_id
+            .get(id)
+            .filter(|post| !post.is_deleted())
+            .ok_or(TransactionError::NotFound)?;
+        if sender != &post.owner {
+            return Err(TransactionError::Unauthorized);
+        }
+        let post = self.post_by_id.remove(id).expect("The post is checked above");
+        if let Some(token) = self.share_token_of(id) {
+            self.share_tokens.remove(&token);
+        }
+        if let Some(expires_at) = post.expires_at {
+            self.expiring.remove(&(expires_at, id.clone()));
+        }
+        self.archived.insert(id.clone(), (post.owner.clone(), archive_blob(&post)));
+        Ok(())
+    }
+
+    /// Restore an archived post of `sender` as it was archived
+    fn unarchive_post(&mut self, sender: &AccountId, id: &PostId) -> TransactionResult {
+        let (owner, blob) = self.archived.get(id).ok_or(TransactionError::NotFound)?;
+        if sender != owner {
+            return Err(TransactionError::Unauthorized);
+        }
+        // the blob is kept if it can't be read, rather than losing the post
+        let post = unarchive_blob(blob).map_err(|err| {
+            log::error!("Archived post {} is unreadable: {:?}", id, err);
+            TransactionError::UnknownError
+        })?;
+        self.archived.remove(id);
+        if let (ExpiryAction::Delete, Some(expires_at)) = (post.on_expiry, post.expires_at) {
+            self.expiring.insert((expires_at, id.clone()));
+        }
+        self.post_by_id.insert(id.clone(), post);
+        Ok(())
+    }
+
+    /// Remove all the posts of `owner`, live, deleted or archived
+    fn clear_posts_of(&mut self, owner: &AccountId) {
+        self.post_by_id.retain(|_, post| &post.owner != owner);
+        self.archived.retain(|_, (archived_owner, _)| archived_owner != owner);
+        self.post_count_by_owner.remove(owner);
+        let post_by_id = &self.post_by_id;
+        self.share_tokens.retain(|_, id| post_by_id.contains_key(id));
+    }
+
+    /// Replace the share token of a private post, or revoke it if `token` is `None`
+    fn set_share_token(
+        &mut self,
+        sender: &AccountId,
+        id: &PostId,
+        token: Option<ShareToken>,
+    ) -> TransactionResult {
+        let post = self
+            .post_by_id
+            .get(id)
+            .filter(|post| !post.is_deleted())
+            .ok_or(TransactionError::NotFound)?;
+        if sender != &post.owner {
+            return Err(TransactionError::Unauthorized);
+        }
+        // a public post is readable by everyone already
+        if token.is_some() && !post.is_private {
+            return Err(TransactionError::BadInput);
+        }
+        match self.share_token_of(id) {
+            Some(old) => {
+                self.share_tokens.remove(&old);
+            }
+            None if token.is_none() => return Err(TransactionError::NotFound),
+            None => (),
+        }
+        if let Some(token) = token {
+            self.share_tokens.insert(token, id.clone());
+        }
+        Ok(())
+    }
+
+    /// All the checks of a post to be created at `created_on` by a sender holding `free_balance`, by `CreatePost` or
+    /// in a `CreatePosts` batch
+    fn validate_new_post(
+        &self,
+        new_post: &NewPost,
+        created_on: CreateOn,
+        free_balance: chain::Balance,
+    ) -> TransactionResult {
+        let owner = AccountId::from(*new_post.owner.as_fixed_bytes());
+        self.check_posting_policy(free_balance)?;
+        self.validate_create(&new_post.id, &owner, &new_post.content)?;
+        self.validate_title(&new_post.title)?;
+        self.validate_public_text(new_post.is_private, &new_post.title, &new_post.content)?;
+        self.validate_metadata(&new_post.metadata)?;
+        self.validate_readers(&readers_of(new_post))?;
+        self.validate_encryption(new_post.encryption.as_ref(), &new_post.content)?;
+        check_content_encoding(new_post.content_encoding, new_post.encryption.as_ref(), &new_post.content)?;
+        self.validate_expiry(new_post.expires_at, created_on)?;
+        self.validate_parent(&new_post.id, &owner, new_post.parent_id.as_ref(), created_on)
+    }
+
+    /// Everything `CreatePost` checks, shared with the `ValidateCreate` dry run so that they can't drift apart
+    ///
+    /// Returns whether the post is already created by a previous delivery of the same Command, which then succeeds
+    /// without changing anything.
+    fn check_create_post(
+        &self,
+        new_post: &NewPost,
+        created_on: CreateOn,
+        free_balance: chain::Balance,
+    ) -> Result<bool, TransactionError> {
+        if self.is_created(new_post) {
+            return Ok(true);
+        }
+        self.validate_new_post(new_post, created_on, free_balance)?;
+        Ok(false)
+    }
+
+    /// The `ValidateCreate` dry run of a `CreatePost` sent by `sender`, with its balance read from `storage`
+    ///
+    /// A Query without an origin is checked as if sent by an account holding nothing.
+    fn dry_run_create(&self, storage: &crate::Storage, sender: Option<&AccountId>, new_post: &NewPost) -> Response {
+        let free_balance = sender.map_or(0, |sender| storage.free_balance(sender));
+        let result = self
+            .check_create_post(new_post, now(), free_balance)
+            .map(|_| ())
+            .map_err(|err| format!("{:?}", err));
+        Response::ValidationResult(result)
+    }
+
+    /// Validate the whole batch before creating anything, so that a rejected batch leaves no partial state
+    ///
+    /// `free_balance` is the balance on chain of the sender of the batch.
+    fn create_posts(
+        &mut self,
+        posts: Vec<NewPost>,
+        created_on: CreateOn,
+        free_balance: chain::Balance,
+    ) -> TransactionResult {
+        if posts.len() > self.config.max_batch_size {
+            return Err(TransactionError::BatchTooLarge);
+        }
+        let mut batch_ids = HashSet::new();
+        let mut batch_count_by_owner: HashMap<AccountId, u32> = HashMap::new();
+        for (index, new_post) in posts.iter().enumerate() {
+            let owner = AccountId::from(*new_post.owner.as_fixed_bytes());
+            let result = self
+                .validate_new_post(new_post, created_on, free_balance)
+                .and_then(|_| {
+                    if !batch_ids.insert(new_post.id.clone()) {
+                        return Err(TransactionError::IdExists);
+                    }
+                    let pending = batch_count_by_owner.entry(owner.clone()).or_default();
+                    *pending += 1;
+                    if self.post_count_of(&owner) + *pending > self.max_posts_per_owner {
+                        return Err(TransactionError::QuotaExceeded);
+                    }
+                    Ok(())
+                });
+            if let Err(err) = result {
+                info!("Batch item {} rejected: {:?}", index, err);
+                return Err(TransactionError::BatchItemRejected(index as u32));
+            }
+        }
+        for new_post in posts {
+            self.insert_post(new_post, created_on);
+        }
+        Ok(())
+    }
+}
+
+impl contracts::NativeContract for Pastebin {
+    type Cmd = Command;
+    type QReq = Request;
+    type QResp = Result<Response, Error>;
+
+    /// Return the contract id which uniquely identifies the contract
+    fn id(&self) -> contracts::ContractId32 {
+        contracts::PASTEBIN
+    }
+
+    /// Acknowledge the Commands so that the clients can confirm e.g. a `CreatePost` has landed
+    fn ack_commands(&self) -> bool {
+        true
+    }
+
+    /// Purge the expired trash at the end of each block
+    fn on_block_end(&mut self, context: &mut NativeContext) {
+        let now = block_timestamp(context);
+        self.last_block = (context.block.block_number, now);
+        self.purge_trash(now);
+        self.sweep_expired(now);
+    }
+
+    /// Handle the Commands from transactions on the blockchain. This method doesn't respond.
+    ///
+    /// # Arguments
+    ///
+    /// * `context` - The current block info with the necessary egress channel
+    /// * `origin` - The sender of the Command, can be Pallet, pRuntime, Contract, Account or even entities from other chain
+    /// * `cmd` - The on-chain Command to process
+    fn handle_command(
+        &mut self,
+        context: &mut NativeContext,
+        origin: MessageOrigin,
+        cmd: Command,
+    ) -> TransactionResult {
+        info!("Command received: {:?}", &cmd);
+
+        // we want to limit the sender who can use the Commands to the pre-define root account
+        let sender = match &origin {
+            MessageOrigin::AccountId(account) => AccountId::from(*account.as_fixed_bytes()),
+            _ => return Err(TransactionError::BadOrigin),
+        };
+        contracts::ensure_not_paused(self.paused, matches!(cmd, Command::SetPaused { .. }))?;
+        let action = audit_action(&cmd);
+        let result = match cmd {
+            Command::SetPaused { paused } => {
+                if sender != self.root {
+                    return Err(TransactionError::Unauthorized);
+                }
+                self.paused = paused;
+                Ok(())
+            }
+            Command::CreatePost(new_post) => {
+                log::info!("New post: {:?}", new_post);
+                let created_on = block_timestamp(context);
+                let free_balance = context.block.storage.free_balance(&sender);
+                if self.check_create_post(&new_post, created_on, free_balance)? {
+                    info!("Post {} already created by a previous delivery of the Command", new_post.id);
+                    return Ok(());
+                }
+                let id = new_post.id.clone();
+                self.insert_post(new_post, created_on);
+                match self.post_by_id.get(&id) {
+                    Some(post) => {
+                        info!("Query received - Create - Post: {:?}", post);
+                    },
+                    None => {
+                        info!("Query received - Create - Post notfound",);
+                    }
+                }
+                Ok(())
+            }
+            Command::GrantAccessBulk { ids, account } => {
+                let account = AccountId::from(*account.as_fixed_bytes());
+                let granted = self.grant_access_bulk(&sender, &ids, &account)?;
+                if granted > 0 {
+                    context.mq().send(&PastebinEvent::AccessGranted {
+                        account: sp_core::H256(account.into()),
+                        granted,
+                    });
+                }
+                Ok(())
+            }
+            Command::TransferContractOwner { new_owner } => {
+                if sender != self.root {
+                    return Err(TransactionError::Unauthorized);
+                }
+                // nobody holds the key of the default account, the root Commands would be locked out
+                if new_owner == Default::default() {
+                    return Err(TransactionError::BadInput);
+                }
+                self.root = AccountId::from(*new_owner.as_fixed_bytes());
+                Ok(())
+            }
+            Command::SetBlockedWords { words } => {
+                if sender != self.root {
+                    return Err(TransactionError::Unauthorized);
+                }
+                if words.len() > MAX_BLOCKED_WORDS
+                    || words.iter().any(|word| word.is_empty() || word.len() > MAX_BLOCKED_WORD_BYTES)
+                {
+                    return Err(TransactionError::BadInput);
+                }
+                self.blocked_words = words.iter().map(|word| word.to_lowercase()).collect();
+                Ok(())
+            }
+            Command::SetPostQuota { max_posts_per_owner } => {
+                if sender != self.root {
+                    return Err(TransactionError::Unauthorized);
+                }
+                self.max_posts_per_owner = max_posts_per_owner;
+                Ok(())
+            }
+            Command::SetMaxTtl {
+                seconds,
+                reject_longer,
+            } => {
+                if sender != self.root {
+                    return Err(TransactionError::Unauthorized);
+                }
+                self.max_ttl = seconds;
+                self.reject_longer_ttl = reject_longer;
+                Ok(())
+            }
+            Command::SetMaxReaders { max_readers } => {
+                if sender != self.root {
+                    return Err(TransactionError::Unauthorized);
+                }
+                self.max_readers = max_readers as usize;
+                Ok(())
+            }
+            Command::SetMinBalanceToPost { min_balance } => {
+                if sender != self.root {
+                    return Err(TransactionError::Unauthorized);
+                }
+                self.min_balance_to_post = min_balance;
+                Ok(())
+            }
+            Command::SetDefaultTtl { seconds } => {
+                if sender != self.root {
+                    return Err(TransactionError::Unauthorized);
+                }
+                self.default_ttl = seconds;
+                Ok(())
+            }
+            Command::AppendToPost { id, extra } => {
+                let post = self
+                    .post_by_id
+                    .get_mut(&id)
+                    .filter(|post| !post.is_deleted())
+                    .ok_or(TransactionError::NotFound)?;
+                if sender != post.owner {
+                    return Err(TransactionError::Unauthorized);
+                }
+                if post.encryption.is_some() {
+                    return Err(TransactionError::ContentEncrypted);
+                }
+                if post.content_len as usize + extra.len() > self.config.max_content_bytes {
+                    return Err(TransactionError::ContentTooLarge(self.config.max_content_bytes as u32));
+                }
+                let mut content = post.content();
+                content.push_str(&extra);
+                check_blocked(&self.blocked_words, post.is_private, &content)?;
+                check_content_encoding(post.content_encoding, None, &content)?;
+                post.edit(content, block_timestamp(context), &self.config);
+                Ok(())
+            }
+            Command::EditPost { id, content } => {
+                let post = self
+                    .post_by_id
+                    .get_mut(&id)
+                    .filter(|post| !post.is_deleted())
+                    .ok_or(TransactionError::NotFound)?;
+                if sender != post.owner {
+                    return Err(TransactionError::Unauthorized);
+                }
+                // editing with the same nonce would break the cipher, the client creates a new post instead
+                if post.encryption.is_some() {
+                    return Err(TransactionError::ContentEncrypted);
+                }
+                if content.len() > self.config.max_content_bytes {
+                    return Err(TransactionError::ContentTooLarge(self.config.max_content_bytes as u32));
+                }
+                check_blocked(&self.blocked_words, post.is_private, &content)?;
+                check_content_encoding(post.content_encoding, None, &content)?;
+                post.edit(content, block_timestamp(context), &self.config);
+                Ok(())
+            }
+            Command::RenamePost { old_id, new_id } => self.rename_post(&sender, &old_id, new_id),
+            Command::ClonePost { source_id, new_id } => {
+                self.check_posting_policy(context.block.storage.free_balance(&sender))?;
+                self.clone_post(&sender, &source_id, new_id, block_timestamp(context))
+            }
+            Command::CreateShareToken { id } => {
+                let token = contracts::try_chain_random(context, &self.share_token_seed(&id))?;
+                self.set_share_token(&sender, &id, Some(token))
+            }
+            Command::RevokeShareToken { id } => self.set_share_token(&sender, &id, None),
+            Command::RecordView { id } => self.record_view(&sender, &id, block_timestamp(context)),
+            Command::SetMaxReads { id, max_reads } => self.set_max_reads(&sender, &id, max_reads),
+            Command::SetPinned { id, pinned } => self.set_pinned(&sender, &id, pinned),
+            Command::ArchivePost { id } => self.archive_post(&sender, &id),
+            Command::UnarchivePost { id } => self.unarchive_post(&sender, &id),
+            Command::PurgeExpired => {
+                let purged = self.purge_expired(&sender, block_timestamp(context));
+                info!("{} expired posts purged", purged);
+                context.mq().send(&PastebinEvent::ExpiredPurged { purged });
+                Ok(())
+            }
+            Command::SetReadNotice { id, enabled } => self.set_read_notice(&sender, &id, enabled),
+            Command::RecordAccess { id } => {
+                if self.record_access(&sender, &id, block_timestamp(context))? {
+                    context.mq().send(&PastebinEvent::AccessNotice {
+                        id,
+                        reader: sp_core::H256(sender.clone().into()),
+                        block: context.block.block_number,
+                    });
+                }
+                Ok(())
+            }
+            Command::SetExpiryAction { id, action } => self.set_expiry_action(&sender, &id, action),
+            Command::ImportPosts {
+                posts,
+                skip_existing,
+            } => {
+                self.check_posting_policy(context.block.storage.free_balance(&sender))?;
+                self.import_posts(&sender, posts, skip_existing, block_timestamp(context))
+            }
+            Command::DeletePost { id } => self.set_deleted(&sender, &id, Some(block_timestamp(context))),
+            Command::RestorePost { id } => self.set_deleted(&sender, &id, None),
+            Command::CreatePosts { posts } => {
+                let free_balance = context.block.storage.free_balance(&sender);
+                self.create_posts(posts, block_timestamp(context), free_balance)
+            }
+            Command::ClearMyPosts => {
+                self.clear_posts_of(&sender);
+                Ok(())
+            }
+            Command::SetMetadata { id, key, value } => {
+                self.validate_metadata_entry(&key, &value)?;
+                let post = self
+                    .post_by_id
+                    .get_mut(&id)
+                    .filter(|post| !post.is_deleted())
+                    .ok_or(TransactionError::NotFound)?;
+                if sender != post.owner {
+                    return Err(TransactionError::Unauthorized);
+                }
+                if !post.metadata.contains_key(&key)
+                    && post.metadata.len() >= self.config.max_metadata_entries
+                {
+                    return Err(TransactionError::MetadataTooLarge);
+                }
+                post.metadata.insert(key, value);
+                post.updated_on = block_timestamp(context);
+                Ok(())
+            }
+            Command::RemoveMetadata { id, key } => {
+                let post = self
+                    .post_by_id
+                    .get_mut(&id)
+                    .filter(|post| !post.is_deleted())
+                    .ok_or(TransactionError::NotFound)?;
+                if sender != post.owner {
+                    return Err(TransactionError::Unauthorized);
+                }
+                post.metadata.remove(&key).ok_or(TransactionError::NotFound)?;
+                post.updated_on = block_timestamp(context);
+                Ok(())
+            }
+        };
+        if let (Ok(_), Some(action)) = (&result, action) {
+            contracts::record_audit(&mut self.audit_log, AuditEntry {
+                block: context.block.block_number,
+                sender,
+                action: action.to_string(),
+            });
+        }
+        result
+    }
+
+    fn error_code(response: &Result<Response, Error>) -> Option<u16> {
+        response.as_ref().err().map(Error::code)
+    }
+
+    fn encode_response(response: &Result<Response, Error>) -> Vec<u8> {
+        contracts::coded_response(response, Error::code)
+    }
+
+    /// Answer `ValidateCreate` with the balance of the sender on chain, and the other Queries with `handle_query`
+    fn handle_query_at(
+        &mut self,
+        storage: &crate::Storage,
+        origin: Option<&chain::AccountId>,
+        req: Request,
+    ) -> Result<Response, Error> {
+        match req {
+            Request::ValidateCreate(new_post) => {
+                info!("Query received: ValidateCreate({})", new_post.id);
+                Ok(self.dry_run_create(storage, origin, &new_post))
+            }
+            req => self.handle_query(origin, req),
+        }
+    }
+
+    /// Handle a direct Query and respond to it. It shouldn't modify the contract state.
+    ///
+    /// # Arguments
+    ///
+    /// * `origin` - For off-chain Query, the sender can only be AccountId
+    /// * `req` — Off-chain Query to handle
+    fn handle_query(
+        &mut self,
+        origin: Option<&chain::AccountId>,
+        req: Request,
+    ) -> Result<Response, Error> {
+        info!("Query received: {:?}", &req);
+        match req {
+            Request::QueryPost { id } => {
+                let sender = origin.ok_or(Error::OriginUnavailable)?;
+                let post = self.read_post(&id, sender)?;
+                Ok(Response::Post(post.view_for(Some(sender))))
+            }
+            Request::QueryStats => {
+                let sender = origin.ok_or(Error::OriginUnavailable)?;
+                let now = now();
+                Ok(Response::Stats {
+                    total_posts: self.post_by_id.values().filter(|post| post.is_live(now)).count() as u32,
+                    owner_posts: self.post_count_of(sender),
+                    max_posts_per_owner: self.max_posts_per_owner,
+                })
+            }
+            // answered by `handle_query_at` with the chain state, no account holds a balance in an empty one
+            Request::ValidateCreate(new_post) => Ok(self.dry_run_create(&Default::default(), origin, &new_post)),
+            Request::IsExpired { id } => {
+                let post = self
+                    .post_by_id
+                    .get(&id)
+                    .filter(|post| !post.is_deleted())
+                    .ok_or(Error::NotFound)?;
+                Ok(Response::Expired(post.is_expired(now())))
+            }
+            Request::PostsByOwner {
+                owner,
+                offset,
+                limit,
+            } => {
+                let sender = origin.ok_or(Error::OriginUnavailable)?;
+                let now = now();
+                let mut posts: Vec<&Post> = self
+                    .post_by_id
+                    .values()
+                    .filter(|post| post.owner == owner && post.is_readable_by(sender))
+                    .filter(|post| !post.is_deleted() && !post.is_exhausted())
+                    .filter(|post| sender == &post.owner || !post.is_expired(now))
+                    .collect();
+                posts.sort_by(|a, b| (!a.pinned, a.created_on, &a.id).cmp(&(!b.pinned, b.created_on, &b.id)));
+                let list = posts
+                    .into_iter()
+                    .skip(offset as usize)
+                    .take(limit.min(self.config.max_page_size) as usize)
+                    .map(Post::summary)
+                    .collect();
+                Ok(Response::PostList(list))
+            }
+            Request::QueryPostSlice { id, offset, length } => {
+                let sender = origin.ok_or(Error::OriginUnavailable)?;
+                let post = self.read_post(&id, sender)?;
+                // slice by characters rather than bytes so that the result never splits a UTF-8 sequence
+                let slice = post
+                    .content()
+                    .chars()
+                    .skip(offset as usize)
+                    .take(length as usize)
+                    .collect();
+                Ok(Response::ContentSlice(slice))
+            }
+            Request::QueryAccessList { id } => {
+                let sender = origin.ok_or(Error::OriginUnavailable)?;
+                let post = self
+                    .post_by_id
+                    .get(&id)
+                    .filter(|post| !post.is_deleted())
+                    .ok_or(Error::NotFound)?;
+                // the readers can read the content, but only the owner can see whom it is shared with
+                if sender != &post.owner {
+                    return Err(Error::NotAuthorized);
+                }
+                Ok(Response::AccessList(post.readable_by.clone()))
+            }
+            Request::PublicFeed { cursor, limit } => {
+                let now = now();
+                let start = match cursor {
+                    Some(cursor) => Bound::Excluded(cursor),
+                    None => Bound::Unbounded,
+                };
+                let limit = limit.min(self.config.max_page_size) as usize;
+                let mut posts: Vec<PostSummary> = self
+                    .post_by_id
+                    .range((start, Bound::Unbounded))
+                    .map(|(_, post)| post)
+                    .filter(|post| !post.is_private && post.is_live(now))
+                    .take(limit + 1)
+                    .map(Post::summary)
+                    .collect();
+                // the extra post only tells whether there is a next page
+                let next_cursor = if posts.len() > limit {
+                    posts.truncate(limit);
+                    posts.last().map(|post| post.id.clone())
+                } else {
+                    None
+                };
+                Ok(Response::PublicFeed { posts, next_cursor })
+            }
+            Request::QueryPostSigned {
+                id,
+                account,
+                signature,
+                timestamp,
+            } => {
+                let now = now();
+                let age = if now > timestamp { now - timestamp } else { timestamp - now };
+                if age > self.config.signature_validity_secs {
+                    return Err(Error::SignatureExpired);
+                }
+                if !signature.verify(&signed_read_payload(&id, timestamp)[..], &account) {
+                    return Err(Error::BadSignature);
+                }
+                let post = self.read_post(&id, &account)?;
+                Ok(Response::Post(post.view_for(Some(&account))))
+            }
+            Request::ServerTime => {
+                let (block_number, block_time) = self.last_block;
+                Ok(Response::Time {
+                    wall_clock: now(),
+                    block_number,
+                    block_time,
+                })
+            }
+            Request::Version => Ok(Response::Version {
+                major: VERSION_MAJOR,
+                minor: VERSION_MINOR,
+            }),
+            Request::QueryHistory { id } => {
+                let sender = origin.ok_or(Error::OriginUnavailable)?;
+                let post = self.read_post(&id, sender)?;
+                Ok(Response::History(post.history.clone()))
+            }
+            Request::QueryPostWithDates {
+                id,
+                utc_offset_minutes,
+            } => {
+                let sender = origin.ok_or(Error::OriginUnavailable)?;
+                let post = self.read_post(&id, sender)?;
+                let dates = PostDates {
+                    created_on: format_timestamp(post.created_on, utc_offset_minutes)?,
+                    updated_on: format_timestamp(post.updated_on, utc_offset_minutes)?,
+                    expires_at: post
+                        .expires_at
+                        .map(|expires_at| format_timestamp(expires_at, utc_offset_minutes))
+                        .transpose()?,
+                };
+                Ok(Response::PostWithDates {
+                    post: post.view_for(Some(sender)),
+                    dates,
+                })
+            }
+            Request::QueryOwnerOf { id } => {
+                let post = self.post_by_id.get(&id).ok_or(Error::NotFound)?;
+                if post.is_expired(now()) || post.is_deleted() {
+                    return Err(Error::NotFound);
+                }
+                Ok(Response::PostOwner(post.owner.clone()))
+            }
+            Request::QueryByTitle { owner, title } => {
+                let sender = origin.ok_or(Error::OriginUnavailable)?;
+                let now = now();
+                let id = self
+                    .post_by_id
+                    .values()
+                    .filter(|post| post.owner == owner && post.title == title && post.is_live(now))
+                    .max_by(|a, b| (a.created_on, &a.id).cmp(&(b.created_on, &b.id)))
+                    .map(|post| post.id.clone())
+                    .ok_or(Error::NotFound)?;
+                let post = self.read_post(&id, sender)?;
+                Ok(Response::Post(post.view_for(Some(sender))))
+            }
+            Request::ExportPosts { owner, cursor } => {
+                let sender = origin.ok_or(Error::OriginUnavailable)?;
+                if sender != &owner {
+                    return Err(Error::NotAuthorized);
+                }
+                let start = match cursor {
+                    Some(cursor) => Bound::Excluded(cursor),
+                    None => Bound::Unbounded,
+                };
+                let mut owned = self
+                    .post_by_id
+                    .range((start, Bound::Unbounded))
+                    .map(|(_, post)| post)
+                    .filter(|post| post.owner == owner && !post.is_deleted() && !post.is_exhausted())
+                    .peekable();
+                let mut posts = vec![];
+                let mut page_bytes = 0;
+                while let Some(post) = owned.peek() {
+                    // always return one post so that a page can't get stuck on a large one
+                    page_bytes += post.encoded_size();
+                    if !posts.is_empty() && page_bytes > self.config.max_export_bytes {
+                        break;
+                    }
+                    posts.push((*post).clone());
+                    owned.next();
+                }
+                let next_cursor = owned
+                    .peek()
+                    .and_then(|_| posts.last().map(|post: &Post| post.id.clone()));
+                Ok(Response::Export { posts, next_cursor })
+            }
+            Request::Exists { id } => Ok(Response::Exists(self.is_id_taken(&id))),
+            Request::PostsInRange {
+                from,
+                to,
+                offset,
+                limit,
+            } => {
+                let sender = origin.ok_or(Error::OriginUnavailable)?;
+                if from > to {
+                    return Err(Error::InvalidRange);
+                }
+                let now = now();
+                let mut posts: Vec<&Post> = self
+                    .post_by_id
+                    .values()
+                    .filter(|post| from <= post.created_on && post.created_on <= to)
+                    .filter(|post| post.is_readable_by(sender) && !post.is_deleted() && !post.is_exhausted())
+                    .filter(|post| sender == &post.owner || !post.is_expired(now))
+                    .collect();
+                posts.sort_by(|a, b| (a.created_on, &a.id).cmp(&(b.created_on, &b.id)));
+                let list = posts
+                    .into_iter()
+                    .skip(offset as usize)
+                    .take(limit.min(self.config.max_page_size) as usize)
+                    .map(Post::summary)
+                    .collect();
+                Ok(Response::PostList(list))
+            }
+            Request::QueryPostByToken { token } => {
+                let post = self
+                    .share_tokens
+                    .get(&token)
+                    .and_then(|id| self.post_by_id.get(id))
+                    .filter(|post| post.is_live(now()))
+                    .ok_or(Error::NotFound)?;
+                Ok(Response::Post(post.view_for(origin)))
+            }
+            Request::QueryShareToken { id } => {
+                let sender = origin.ok_or(Error::OriginUnavailable)?;
+                let post = self.read_post(&id, sender)?;
+                if sender != &post.owner {
+                    return Err(Error::NotAuthorized);
+                }
+                Ok(Response::ShareToken(self.share_token_of(&id)))
+            }
+            Request::QueryPostChunk {
+                id,
+                chunk_index,
+                chunk_size,
+            } => {
+                let sender = origin.ok_or(Error::OriginUnavailable)?;
+                if chunk_size == 0 || chunk_size > self.config.max_chunk_bytes {
+                    return Err(Error::InvalidChunkSize);
+                }
+                let content = self.read_post(&id, sender)?.content().into_bytes();
+                let start = (chunk_index as usize)
+                    .saturating_mul(chunk_size as usize)
+                    .min(content.len());
+                let end = start.saturating_add(chunk_size as usize).min(content.len());
+                Ok(Response::Chunk {
+                    data: content[start..end].to_vec(),
+                    is_last: end == content.len(),
+                })
+            }
+            Request::QueryPosts { ids } => {
+                let sender = origin.ok_or(Error::OriginUnavailable)?;
+                if ids.len() > self.config.max_page_size as usize {
+                    return Err(Error::BatchTooLarge);
+                }
+                let posts = ids
+                    .iter()
+                    .map(|id| self.read_post(id, sender).map(|post| post.view_for(Some(sender))))
+                    .collect();
+                Ok(Response::Posts(posts))
+            }
+            Request::Oldest { owner } => {
+                let post = self
+                    .scoped_posts(owner.as_ref(), origin, now())
+                    .min_by(|a, b| (a.created_on, &a.id).cmp(&(b.created_on, &b.id)))
+                    .ok_or(Error::NotFound)?;
+                Ok(Response::PostSummary(post.summary()))
+            }
+            Request::Newest { owner } => {
+                let post = self
+                    .scoped_posts(owner.as_ref(), origin, now())
+                    .max_by(|a, b| (a.created_on, &a.id).cmp(&(b.created_on, &b.id)))
+                    .ok_or(Error::NotFound)?;
+                Ok(Response::PostSummary(post.summary()))
+            }
+            Request::CanRead { id, account } => {
+                let sender = origin.ok_or(Error::OriginUnavailable)?;
+                let post = self
+                    .post_by_id
+                    .get(&id)
+                    .filter(|post| !post.is_expired(now()) && !post.is_deleted())
+                    .ok_or(Error::NotFound)?;
+                if post.is_private && sender != &post.owner {
+                    return Err(Error::NotAuthorized);
+                }
+                Ok(Response::CanRead(post.is_readable_by(&account)))
+            }
+            Request::QueryReplies { id, offset, limit } => {
+                let sender = origin.ok_or(Error::OriginUnavailable)?;
+                self.read_post(&id, sender)?;
+                let now = now();
+                let mut replies: Vec<&Post> = self
+                    .post_by_id
+                    .values()
+                    .filter(|post| post.parent_id.as_ref() == Some(&id) && post.is_readable_by(sender))
+                    .filter(|post| !post.is_deleted() && !post.is_exhausted())
+                    .filter(|post| sender == &post.owner || !post.is_expired(now))
+                    .collect();
+                replies.sort_by(|a, b| (a.created_on, &a.id).cmp(&(b.created_on, &b.id)));
+                let list = replies
+                    .into_iter()
+                    .skip(offset as usize)
+                    .take(limit.min(self.config.max_page_size) as usize)
+                    .map(Post::summary)
+                    .collect();
+                Ok(Response::PostList(list))
+            }
+            Request::QueryAuditLog { offset, limit } => {
+                let sender = origin.ok_or(Error::OriginUnavailable)?;
+                if sender != &self.root {
+                    return Err(Error::NotAuthorized);
+                }
+                let limit = limit.min(self.config.max_page_size);
+                Ok(Response::AuditLog(contracts::audit_log_page(&self.audit_log, offset, limit)))
+            }
+            Request::QueryPostRaw { id } => {
+                let sender = origin.ok_or(Error::OriginUnavailable)?;
+                let post = self.read_post(&id, sender)?;
+                let mut post = post.clone();
+                post.hide_readers_from(Some(sender));
+                Ok(Response::RawPost(post.encode()))
+            }
+            Request::QueryContentHash { id } => {
+                let sender = origin.ok_or(Error::OriginUnavailable)?;
+                let post = self.read_post(&id, sender)?;
+                Ok(Response::ContentHash(hashing::blake2_256(post.content().as_bytes())))
+            }
+            Request::QueryDiff {
+                id,
+                from_version,
+                to_version,
+            } => {
+                let sender = origin.ok_or(Error::OriginUnavailable)?;
+                let post = self.read_post(&id, sender)?;
+                let from = post.content_at(from_version).ok_or(Error::InvalidVersion)?;
+                let to = post.content_at(to_version).ok_or(Error::InvalidVersion)?;
+                let (lines, truncated) = line_diff(&from, &to, self.config.max_diff_lines);
+                Ok(Response::Diff { lines, truncated })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post_with_content(content: &str) -> Post {
+        let mut post = Post::default();
+        post.set_content(content.to_string());
+        post
+    }
+
+    /// A post of the account `[1u8; 32]`, shared with nobody if `is_private`
+    fn new_post(id: &str, content: &str, is_private: bool) -> NewPost {
+        let new_post = NewPost::new(id.to_string(), sp_core::H256([1u8; 32]), content.to_string());
+        if is_private {
+            new_post.with_readers(vec![])
+        } else {
+            new_post
+        }
+    }
+
+    #[test]
+    fn compressible_content_is_stored_compressed() {
+        let content = "top secret markdown\n".repeat(1000);
+        let post = post_with_content(&content);
+        assert!(post.compressed);
+        assert!(post.content.len() < content.len());
+        assert_eq!(post.content_len as usize, content.len());
+        assert_eq!(post.content(), content);
+        assert_eq!(post.decompressed().content, content.as_bytes());
+    }
+
+    #[test]
+    fn incompressible_content_round_trips() {
+        // xorshift generated text, which deflate can barely shrink
+        let mut seed = 0x2545_f491_u32;
+        let content: String = (0..8 * 1024)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                std::char::from_u32(0x4e00 + seed % 0x5000).unwrap()
+            })
+            .collect();
+        let post = post_with_content(&content);
+        assert!(post.content.len() <= content.len());
+        assert_eq!(post.content_len as usize, content.len());
+        assert_eq!(post.content(), content);
+    }
+
+    #[test]
+    fn small_content_is_stored_plain() {
+        let post = post_with_content("hello");
+        assert!(!post.compressed);
+        assert_eq!(post.content, b"hello");
+    }
+
+    #[test]
+    fn size_limit_applies_before_compression() {
+        let pastebin = Pastebin::new_dev();
+        let owner = AccountId::new([1u8; 32]);
+        let id = PostId::from("big");
+        let content = "a".repeat(MAX_CONTENT_BYTES + 1);
+        assert!(post_with_content(&content).content.len() < MAX_CONTENT_BYTES);
+        assert!(matches!(
+            pastebin.validate_create(&id, &owner, &content),
+            Err(TransactionError::ContentTooLarge(limit)) if limit as usize == MAX_CONTENT_BYTES
+        ));
+        let content = "a".repeat(MAX_CONTENT_BYTES);
+        assert!(pastebin.validate_create(&id, &owner, &content).is_ok());
+    }
+
+    #[test]
+    fn ids_follow_the_policy() {
+        let pastebin = Pastebin::new_dev();
+        let owner = AccountId::new([1u8; 32]);
+        let check = |id: &str| pastebin.validate_create(&id.to_string(), &owner, &"hello".to_string());
+        assert!(check("my-post_2").is_ok());
+        assert!(check(&"a".repeat(MAX_ID_LEN)).is_ok());
+        for id in ["", "two words", "a/b", "ünïcode", "dot.ted"].iter() {
+            assert!(matches!(check(id), Err(TransactionError::InvalidInput)), "{:?}", id);
+        }
+        assert!(matches!(
+            check(&"a".repeat(MAX_ID_LEN + 1)),
+            Err(TransactionError::InvalidInput)
+        ));
+        assert_eq!(TransactionError::InvalidInput.code(), 400);
+    }
+
+    #[test]
+    fn content_hash_ignores_compression() {
+        use contracts::NativeContract;
+        let owner = AccountId::new([1u8; 32]);
+        let mut pastebin = Pastebin::new_dev();
+        let content = "a".repeat(COMPRESSION_THRESHOLD * 2);
+        pastebin.insert_post(
+            NewPost::new("big".to_string(), sp_core::H256([1u8; 32]), content.clone()).with_readers(vec![]),
+            now(),
+        );
+        assert!(pastebin.post_by_id["big"].compressed);
+        let query = |pastebin: &mut Pastebin, sender: &AccountId| {
+            pastebin.handle_query(Some(sender), Request::QueryContentHash { id: "big".to_string() })
+        };
+        assert!(matches!(
+            query(&mut pastebin, &owner),
+            Ok(Response::ContentHash(hash)) if hash == hashing::blake2_256(content.as_bytes())
+        ));
+        assert!(matches!(
+            query(&mut pastebin, &AccountId::new([2u8; 32])),
+            Err(Error::NotAuthorized)
+        ));
+    }
+
+    #[test]
+    fn posts_are_hidden_after_max_reads() {
+        use contracts::NativeContract;
+        let owner = AccountId::new([1u8; 32]);
+        let reader = AccountId::new([2u8; 32]);
+        let mut pastebin = Pastebin::new_dev();
+        pastebin.insert_post(
+            NewPost::new("limited".to_string(), sp_core::H256([1u8; 32]), "hello".to_string()),
+            now(),
+        );
+        let id = "limited".to_string();
+        assert!(matches!(
+            pastebin.set_max_reads(&reader, &id, Some(2)),
+            Err(TransactionError::Unauthorized)
+        ));
+        assert!(matches!(
+            pastebin.set_max_reads(&owner, &id, Some(0)),
+            Err(TransactionError::BadInput)
+        ));
+        pastebin.set_max_reads(&owner, &id, Some(2)).unwrap();
+
+        // the owner's reads are not counted
+        pastebin.record_view(&owner, &id, now()).unwrap();
+        pastebin.record_view(&reader, &id, now()).unwrap();
+        assert!(pastebin.read_post(&id, &reader).is_ok());
+        pastebin.record_view(&reader, &id, now()).unwrap();
+        assert!(matches!(pastebin.read_post(&id, &reader), Err(Error::NotFound)));
+        assert!(matches!(
+            pastebin.record_view(&reader, &id, now()),
+            Err(TransactionError::NotFound)
+        ));
+        assert!(matches!(
+            pastebin.handle_query(Some(&owner), Request::QueryPost { id: id.clone() }),
+            Err(Error::NotFound)
+        ));
+
+        // nor through its title or a share token
+        assert!(matches!(
+            pastebin.handle_query(
+                Some(&reader),
+                Request::QueryByTitle {
+                    owner: owner.clone(),
+                    title: Default::default(),
+                },
+            ),
+            Err(Error::NotFound)
+        ));
+        pastebin.share_tokens.insert([7u8; 32], id.clone());
+        assert!(matches!(
+            pastebin.handle_query(None, Request::QueryPostByToken { token: [7u8; 32] }),
+            Err(Error::NotFound)
+        ));
+
+        // raising the limit makes the post readable again
+        pastebin.set_max_reads(&owner, &id, None).unwrap();
+        assert!(pastebin.read_post(&id, &reader).is_ok());
+        assert!(matches!(
+            pastebin.handle_query(None, Request::QueryPostByToken { token: [7u8; 32] }),
+            Ok(Response::Post(_))
+        ));
+    }
+
+    /// A pastebin with the public posts "live", "reply" to it and "spent", created in this order, "spent" exhausted
+    fn with_an_exhausted_post() -> Pastebin {
+        let mut pastebin = Pastebin::new_dev();
+        pastebin.insert_post(new_post("live", "hello", false), 100);
+        pastebin.insert_post(new_post("reply", "hello", false).with_parent("live".to_string()), 200);
+        pastebin.insert_post(new_post("spent", "hello", false).with_parent("live".to_string()), 300);
+        exhaust(&mut pastebin, "spent");
+        pastebin
+    }
+
+    fn exhaust(pastebin: &mut Pastebin, id: &str) {
+        let id = id.to_string();
+        pastebin
+            .set_max_reads(&AccountId::new([1u8; 32]), &id, Some(1))
+            .unwrap();
+        pastebin.record_view(&AccountId::new([2u8; 32]), &id, now()).unwrap();
+    }
+
+    fn listed_ids(response: Result<Response, Error>) -> Vec<PostId> {
+        match response {
+            Ok(Response::PostList(list)) => list.into_iter().map(|post| post.id).collect(),
+            Ok(Response::PublicFeed { posts, .. }) => posts.into_iter().map(|post| post.id).collect(),
+            Ok(Response::Export { posts, .. }) => posts.into_iter().map(|post| post.id).collect(),
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn posts_by_owner_hides_exhausted_posts() {
+        use contracts::NativeContract;
+        let owner = AccountId::new([1u8; 32]);
+        let mut pastebin = with_an_exhausted_post();
+        let request = Request::PostsByOwner {
+            owner: owner.clone(),
+            offset: 0,
+            limit: 10,
+        };
+        assert_eq!(
+            listed_ids(pastebin.handle_query(Some(&owner), request)),
+            vec!["live", "reply"]
+        );
+    }
+
+    #[test]
+    fn posts_in_range_hides_exhausted_posts() {
+        use contracts::NativeContract;
+        let mut pastebin = with_an_exhausted_post();
+        let request = Request::PostsInRange {
+            from: 200,
+            to: 300,
+            offset: 0,
+            limit: 10,
+        };
+        assert_eq!(
+            listed_ids(pastebin.handle_query(Some(&AccountId::new([1u8; 32])), request)),
+            vec!["reply"]
+        );
+    }
+
+    #[test]
+    fn public_feed_hides_exhausted_posts() {
+        use contracts::NativeContract;
+        let mut pastebin = with_an_exhausted_post();
+        let request = Request::PublicFeed {
+            cursor: None,
+            limit: 10,
+        };
+        assert_eq!(listed_ids(pastebin.handle_query(None, request)), vec!["live", "reply"]);
+    }
+
+    #[test]
+    fn replies_hide_exhausted_posts() {
+        use contracts::NativeContract;
+        let mut pastebin = with_an_exhausted_post();
+        let request = Request::QueryReplies {
+            id: "live".to_string(),
+            offset: 0,
+            limit: 10,
+        };
+        assert_eq!(
+            listed_ids(pastebin.handle_query(Some(&AccountId::new([1u8; 32])), request)),
+            vec!["reply"]
+        );
+    }
+
+    #[test]
+    fn export_hides_exhausted_posts() {
+        use contracts::NativeContract;
+        let owner = AccountId::new([1u8; 32]);
+        let mut pastebin = with_an_exhausted_post();
+        let request = Request::ExportPosts {
+            owner: owner.clone(),
+            cursor: None,
+        };
+        assert_eq!(
+            listed_ids(pastebin.handle_query(Some(&owner), request)),
+            vec!["live", "reply"]
+        );
+    }
+
+    #[test]
+    fn oldest_and_newest_skip_exhausted_posts() {
+        use contracts::NativeContract;
+        let owner = AccountId::new([1u8; 32]);
+        let mut pastebin = with_an_exhausted_post();
+        let found = |pastebin: &mut Pastebin, request| match pastebin.handle_query(Some(&owner), request) {
+            Ok(Response::PostSummary(post)) => post.id,
+            other => panic!("unexpected response: {:?}", other),
+        };
+        assert_eq!(
+            found(
+                &mut pastebin,
+                Request::Newest {
+                    owner: Some(owner.clone())
+                }
+            ),
+            "reply"
+        );
+        assert_eq!(found(&mut pastebin, Request::Newest { owner: None }), "reply");
+        exhaust(&mut pastebin, "live");
+        assert_eq!(
+            found(
+                &mut pastebin,
+                Request::Oldest {
+                    owner: Some(owner.clone())
+                }
+            ),
+            "reply"
+        );
+        assert_eq!(found(&mut pastebin, Request::Oldest { owner: None }), "reply");
+    }
+
+    #[test]
+    fn pinned_posts_are_listed_first() {
+        use contracts::NativeContract;
+        let owner = AccountId::new([1u8; 32]);
+        let other = AccountId::new([2u8; 32]);
+        let mut pastebin = Pastebin::new(PastebinConfig {
+            max_pinned_posts: 1,
+            ..Default::default()
+        });
+        for (created_on, id) in ["a", "b", "c"].iter().enumerate() {
+            let new_post = NewPost::new(id.to_string(), sp_core::H256([1u8; 32]), "hello".to_string());
+            pastebin.insert_post(new_post, created_on as u64);
+        }
+        let listed_ids = |pastebin: &mut Pastebin| match pastebin.handle_query(
+            Some(&owner),
+            Request::PostsByOwner {
+                owner: owner.clone(),
+                offset: 0,
+                limit: 10,
+            },
+        ) {
+            Ok(Response::PostList(list)) => list.into_iter().map(|post| post.id).collect::<Vec<_>>(),
+            other => panic!("unexpected response: {:?}", other),
+        };
+
+        let (b, c) = ("b".to_string(), "c".to_string());
+        assert!(matches!(
+            pastebin.set_pinned(&other, &c, true),
+            Err(TransactionError::Unauthorized)
+        ));
+        pastebin.set_pinned(&owner, &c, true).unwrap();
+        // pinning a pinned post doesn't count twice
+        pastebin.set_pinned(&owner, &c, true).unwrap();
+        assert_eq!(listed_ids(&mut pastebin), vec!["c", "a", "b"]);
+        assert!(matches!(
+            pastebin.set_pinned(&owner, &b, true),
+            Err(TransactionError::QuotaExceeded)
+        ));
+
+        // a deleted post gives its pin back
+        pastebin.set_deleted(&owner, &c, Some(now())).unwrap();
+        pastebin.set_pinned(&owner, &b, true).unwrap();
+        pastebin.set_deleted(&owner, &c, None).unwrap();
+        assert_eq!(listed_ids(&mut pastebin), vec!["b", "a", "c"]);
+        pastebin.set_pinned(&owner, &b, false).unwrap();
+        assert_eq!(listed_ids(&mut pastebin), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn readers_are_stored_without_duplicates_nor_owner() {
+        let owner = AccountId::new([1u8; 32]);
+        let (alice, bob) = (AccountId::new([2u8; 32]), AccountId::new([3u8; 32]));
+        let mut pastebin = Pastebin::new_dev();
+        pastebin.max_readers = 2;
+        let readers = vec![[2u8; 32], [1u8; 32], [3u8; 32], [2u8; 32], [3u8; 32]];
+        let new_post = NewPost::new("shared".to_string(), sp_core::H256([1u8; 32]), "hello".to_string())
+            .with_readers(readers.into_iter().map(sp_core::H256).collect());
+        // only the distinct readers other than the owner count towards `max_readers`
+        pastebin.validate_new_post(&new_post, now(), 0).unwrap();
+        pastebin.insert_post(new_post, now());
+        let id = "shared".to_string();
+        assert_eq!(pastebin.post_by_id[&id].readable_by, vec![alice.clone(), bob.clone()]);
+
+        pastebin.max_readers = 3;
+        let ids = vec![id.clone()];
+        assert_eq!(pastebin.grant_access_bulk(&owner, &ids, &alice).unwrap(), 0);
+        assert_eq!(pastebin.grant_access_bulk(&owner, &ids, &owner).unwrap(), 0);
+        assert_eq!(pastebin.post_by_id[&id].readable_by, vec![alice, bob]);
+    }
+
+    #[test]
+    fn reads_are_reported_once_enabled_by_the_owner() {
+        let owner = AccountId::new([1u8; 32]);
+        let reader = AccountId::new([2u8; 32]);
+        let stranger = AccountId::new([3u8; 32]);
+        let mut pastebin = Pastebin::new_dev();
+        let new_post = NewPost::new("secret".to_string(), sp_core::H256([1u8; 32]), "hello".to_string())
+            .with_readers(vec![sp_core::H256([2u8; 32])]);
+        pastebin.insert_post(new_post, now());
+        let id = "secret".to_string();
+        assert!(!pastebin.record_access(&reader, &id, now()).unwrap());
+
+        assert!(matches!(
+            pastebin.set_read_notice(&reader, &id, true),
+            Err(TransactionError::Unauthorized)
+        ));
+        pastebin.set_read_notice(&owner, &id, true).unwrap();
+        assert!(pastebin.record_access(&reader, &id, now()).unwrap());
+        assert!(!pastebin.record_access(&owner, &id, now()).unwrap());
+        assert!(matches!(
+            pastebin.record_access(&stranger, &id, now()),
+            Err(TransactionError::Unauthorized)
+        ));
+        let missing = "missing".to_string();
+        assert!(matches!(
+            pastebin.record_access(&reader, &missing, now()),
+            Err(TransactionError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn posting_needs_the_min_balance_once_set() {
+        let mut pastebin = Pastebin::new_dev();
+        assert!(pastebin.check_posting_policy(0).is_ok());
+
+        pastebin.min_balance_to_post = Some(100);
+        assert!(matches!(
+            pastebin.check_posting_policy(99),
+            Err(TransactionError::Unauthorized)
+        ));
+        assert!(pastebin.check_posting_policy(100).is_ok());
+        assert_eq!(
+            audit_action(&Command::SetMinBalanceToPost { min_balance: None }),
+            Some("SetMinBalanceToPost")
+        );
+    }
+
+    #[test]
+    fn only_an_identical_create_is_a_retry() {
+        let mut pastebin = Pastebin::new_dev();
+        let owner = sp_core::H256([1u8; 32]);
+        let new_post = NewPost::new("post".to_string(), owner, "hello".to_string())
+            .with_readers(vec![sp_core::H256([2u8; 32])])
+            .with_title("title".to_string());
+        pastebin.insert_post(new_post.clone(), 1_000);
+
+        assert!(pastebin.check_create_post(&new_post, 2_000, 0).unwrap());
+        let mut public = new_post.clone();
+        public.is_private = false;
+        public.readable_by.clear();
+        let changed = [
+            public,
+            new_post.clone().with_readers(vec![sp_core::H256([3u8; 32])]),
+            new_post.clone().with_expiry(5_000),
+            new_post.clone().with_metadata("key".to_string(), "value".to_string()),
+            new_post.clone().with_content_encoding(ContentEncoding::Hex),
+        ];
+        for new_post in &changed {
+            assert!(matches!(
+                pastebin.check_create_post(new_post, 2_000, 0),
+                Err(TransactionError::IdExists)
+            ));
+        }
+    }
+
+    #[test]
+    fn dry_run_applies_all_the_create_checks() {
+        use contracts::NativeContract;
+        let mut pastebin = Pastebin::new_dev();
+        let owner = sp_core::H256([1u8; 32]);
+        let dry_run = |pastebin: &mut Pastebin, new_post: &NewPost| match pastebin
+            .handle_query(None, Request::ValidateCreate(new_post.clone()))
+        {
+            Ok(Response::ValidationResult(result)) => result,
+            other => panic!("unexpected response: {:?}", other),
+        };
+        let new_post = NewPost::new("post".to_string(), owner, "hello".to_string());
+        assert_eq!(dry_run(&mut pastebin, &new_post), Ok(()));
+
+        pastebin.blocked_words.insert("hello".to_string());
+        assert!(dry_run(&mut pastebin, &new_post).is_err());
+        assert!(dry_run(&mut pastebin, &new_post.clone().with_readers(vec![])).is_ok());
+        pastebin.blocked_words.clear();
+
+        let bad_metadata = new_post
+            .clone()
+            .with_metadata("k".repeat(MAX_METADATA_KEY_BYTES + 1), String::new());
+        assert!(dry_run(&mut pastebin, &bad_metadata).is_err());
+
+        // the empty chain state of a test holds no balance
+        pastebin.min_balance_to_post = Some(1);
+        assert!(dry_run(&mut pastebin, &new_post).is_err());
+    }
+
+    #[test]
+    fn raw_posts_decode_back_to_the_stored_post() {
+        use contracts::NativeContract;
+        let reader = AccountId::new([2u8; 32]);
+        let mut pastebin = Pastebin::new_dev();
+        let content = "a".repeat(COMPRESSION_THRESHOLD * 2);
+        let new_post = NewPost::new("big".to_string(), sp_core::H256([1u8; 32]), content.clone())
+            .with_readers(vec![sp_core::H256([2u8; 32])]);
+        pastebin.insert_post(new_post, now());
+        let query = |pastebin: &mut Pastebin, sender: &AccountId| {
+            pastebin.handle_query(Some(sender), Request::QueryPostRaw { id: "big".to_string() })
+        };
+        assert!(matches!(
+            query(&mut pastebin, &AccountId::new([3u8; 32])),
+            Err(Error::NotAuthorized)
+        ));
+
+        let raw = match query(&mut pastebin, &reader) {
+            Ok(Response::RawPost(raw)) => raw,
+            other => panic!("unexpected response: {:?}", other),
+        };
+        let post = decode_raw_post(&raw, MAX_CONTENT_BYTES).unwrap();
+        assert!(post.readable_by.is_empty());
+        match query(&mut pastebin, &AccountId::new([1u8; 32])) {
+            Ok(Response::RawPost(raw)) => assert_eq!(raw, pastebin.post_by_id["big"].encode()),
+            other => panic!("unexpected response: {:?}", other),
+        }
+        assert!(!post.compressed);
+        assert_eq!(post.content(), content);
+        assert!(decode_raw_post(&raw, COMPRESSION_THRESHOLD).is_err());
+        assert!(decode_raw_post(&[raw.clone(), vec![0]].concat(), MAX_CONTENT_BYTES).is_err());
+    }
+
+    #[test]
+    fn expired_posts_are_purged_on_demand() {
+        let owner = AccountId::new([1u8; 32]);
+        let other = AccountId::new([2u8; 32]);
+        let mut pastebin = Pastebin::new_dev();
+        let root = pastebin.root.clone();
+        for (id, owner, expires_at) in [("a", [1u8; 32], 10), ("b", [1u8; 32], 30), ("c", [2u8; 32], 10)].iter() {
+            let new_post =
+                NewPost::new(id.to_string(), sp_core::H256(*owner), "hello".to_string()).with_expiry(*expires_at);
+            pastebin.insert_post(new_post, 0);
+        }
+        pastebin.set_deleted(&other, &"c".to_string(), Some(5)).unwrap();
+
+        // only the own expired posts of an owner
+        assert_eq!(pastebin.purge_expired(&owner, 20), 1);
+        assert!(!pastebin.post_by_id.contains_key("a"));
+        assert_eq!(pastebin.post_count_of(&owner), 1);
+        // the root account purges the expired posts of everyone, deleted or not
+        assert_eq!(pastebin.purge_expired(&root, 20), 1);
+        assert!(!pastebin.post_by_id.contains_key("c"));
+        assert_eq!(pastebin.post_count_of(&other), 0);
+        assert_eq!(pastebin.purge_expired(&root, 20), 0);
+        assert!(pastebin.post_by_id.contains_key("b"));
+    }
+
+    #[test]
+    fn archived_posts_keep_their_id_until_restored() {
+        use contracts::NativeContract;
+        let owner = AccountId::new([1u8; 32]);
+        let other = AccountId::new([2u8; 32]);
+        let mut pastebin = Pastebin::new_dev();
+        let content = "a".repeat(COMPRESSION_THRESHOLD * 2);
+        let new_post = NewPost::new("old".to_string(), sp_core::H256([1u8; 32]), content.clone());
+        pastebin.insert_post(new_post, now());
+        let id = "old".to_string();
+
+        assert!(matches!(
+            pastebin.archive_post(&other, &id),
+            Err(TransactionError::Unauthorized)
+        ));
+        pastebin.archive_post(&owner, &id).unwrap();
+        assert!(!pastebin.post_by_id.contains_key(&id));
+        assert!(pastebin.archived[&id].1.len() < COMPRESSION_THRESHOLD);
+        assert!(matches!(
+            pastebin.handle_query(Some(&owner), Request::QueryPost { id: id.clone() }),
+            Err(Error::Archived)
+        ));
+        assert!(matches!(
+            pastebin.validate_create(&id, &owner, &content),
+            Err(TransactionError::IdExists)
+        ));
+        assert_eq!(pastebin.post_count_of(&owner), 1);
+
+        assert!(matches!(
+            pastebin.unarchive_post(&other, &id),
+            Err(TransactionError::Unauthorized)
+        ));
+        pastebin.unarchive_post(&owner, &id).unwrap();
+        assert_eq!(pastebin.read_post(&id, &owner).unwrap().content(), content);
+        assert!(matches!(
+            pastebin.unarchive_post(&owner, &id),
+            Err(TransactionError::NotFound)
+        ));
+
+        // an unreadable blob fails the restore instead of panicking
+        pastebin.archive_post(&owner, &id).unwrap();
+        pastebin.archived.get_mut(&id).unwrap().1 = b"garbage".to_vec();
+        assert!(pastebin.unarchive_post(&owner, &id).is_err());
+        assert!(pastebin.archived.contains_key(&id));
+
+        // the archived posts are cleared with the others of their owner only
+        let new_post = NewPost::new("theirs".to_string(), sp_core::H256([2u8; 32]), "hello".to_string());
+        pastebin.insert_post(new_post, now());
+        pastebin.archive_post(&other, &"theirs".to_string()).unwrap();
+        pastebin.clear_posts_of(&owner);
+        assert!(!pastebin.is_id_taken(&id));
+        assert!(pastebin.archived.contains_key("theirs"));
+    }
+
+    #[test]
+    fn max_readers_applies_to_all_the_grants() {
+        let owner = AccountId::new([1u8; 32]);
+        let mut pastebin = Pastebin::new_dev();
+        pastebin.max_readers = 1;
+        let new_post = NewPost::new("shared".to_string(), sp_core::H256([1u8; 32]), "hello".to_string())
+            .with_readers(vec![sp_core::H256([2u8; 32]), sp_core::H256([3u8; 32])]);
+        assert!(matches!(
+            pastebin.validate_new_post(&new_post, now(), 0),
+            Err(TransactionError::TooManyReaders)
+        ));
+
+        let new_post = new_post.with_readers(vec![sp_core::H256([2u8; 32])]);
+        pastebin.validate_new_post(&new_post, now(), 0).unwrap();
+        pastebin.insert_post(new_post, now());
+        let ids = vec!["shared".to_string()];
+        assert_eq!(
+            pastebin
+                .grant_access_bulk(&owner, &ids, &AccountId::new([3u8; 32]))
+                .unwrap(),
+            0
+        );
+        pastebin.max_readers = 2;
+        assert_eq!(
+            pastebin
+                .grant_access_bulk(&owner, &ids, &AccountId::new([3u8; 32]))
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            audit_action(&Command::SetMaxReaders { max_readers: 2 }),
+            Some("SetMaxReaders")
+        );
+    }
+
+    #[test]
+    fn server_time_reports_the_last_block() {
+        use contracts::NativeContract;
+        let mut pastebin = Pastebin::new_dev();
+        pastebin.last_block = (42, 1_000);
+        match pastebin.handle_query(None, Request::ServerTime) {
+            Ok(Response::Time {
+                wall_clock,
+                block_number: 42,
+                block_time: 1_000,
+            }) => assert!(wall_clock >= now() - 1),
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn binary_contents_must_match_their_encoding() {
+        let check = |encoding: ContentEncoding, content: &str| check_content_encoding(encoding, None, content).is_ok();
+        assert!(check(ContentEncoding::Utf8, "not base64!"));
+        assert!(check(ContentEncoding::Base64, &base64::encode([1u8, 2, 3, 4])));
+        assert!(!check(ContentEncoding::Base64, "not base64!"));
+        assert!(check(ContentEncoding::Hex, "00ff"));
+        assert!(!check(ContentEncoding::Hex, "0ff"));
+        assert!(!check(ContentEncoding::Hex, "zz"));
+
+        let pastebin = Pastebin::new_dev();
+        let new_post = NewPost::new("key".to_string(), sp_core::H256([1u8; 32]), "zz".to_string())
+            .with_content_encoding(ContentEncoding::Hex);
+        assert!(matches!(
+            pastebin.validate_new_post(&new_post, now(), 0),
+            Err(TransactionError::BadInput)
+        ));
+    }
+
+    #[test]
+    fn create_post_keeps_the_encoding_of_its_fields() {
+        let owner = sp_core::H256([1u8; 32]);
+        let new_post = NewPost::new("id".to_string(), owner, "hello".to_string())
+            .with_title("title".to_string())
+            .with_expiry(100);
+        assert!(!new_post.is_private);
+        let fields = (
+            0u8,
+            "id",
+            owner,
+            false,
+            Vec::<sp_core::H256>::new(),
+            "hello",
+            "title",
+            Some(100u64),
+            Metadata::new(),
+            None::<EncryptionMeta>,
+            None::<PostId>,
+            ContentEncoding::Utf8,
+        );
+        assert_eq!(Command::CreatePost(new_post).encode(), fields.encode());
+    }
+
+    #[test]
+    fn titles_reject_control_characters() {
+        let pastebin = Pastebin::new_dev();
+        assert!(pastebin.validate_title(&"Notes – ünïcode ✅".to_string()).is_ok());
+        assert!(matches!(
+            pastebin.validate_title(&"two\nlines".to_string()),
+            Err(TransactionError::InvalidInput)
+        ));
+        assert!(matches!(
+            pastebin.validate_title(&"bell\u{7}".to_string()),
+            Err(TransactionError::InvalidInput)
+        ));
+    }
+
+    #[test]
+    fn blocked_words_only_apply_to_public_posts() {
+        let mut pastebin = Pastebin::new_dev();
+        pastebin.blocked_words = ["spam"].iter().map(|word| word.to_string()).collect();
+        let check = |is_private: bool, title: &str, content: &str| {
+            pastebin.validate_public_text(is_private, &title.to_string(), &content.to_string())
+        };
+
+        assert!(matches!(
+            check(false, "Buy SPAM now", ""),
+            Err(TransactionError::Blocked)
+        ));
+        assert!(matches!(check(false, "", "more Spam"), Err(TransactionError::Blocked)));
+        assert!(check(false, "ham", "eggs").is_ok());
+        assert!(check(true, "spam", "spam").is_ok());
+    }
+
+    #[test]
+    fn signed_query_reads_private_post() {
+        use contracts::NativeContract;
+        use sp_core::{sr25519, Pair};
+
+        let pair = sr25519::Pair::from_seed(&[7u8; 32]);
+        let account = AccountId::from(pair.public());
+        let mut pastebin = Pastebin::new_dev();
+        pastebin.insert_post(
+            NewPost::new("secret".to_string(), pair.public().into(), "hello".to_string()).with_readers(vec![]),
+            now(),
+        );
+        let mut query = |signer: &sr25519::Pair, timestamp: u64| {
+            let signature = signer.sign(&signed_read_payload("secret", timestamp));
+            pastebin.handle_query(
+                None,
+                Request::QueryPostSigned {
+                    id: "secret".to_string(),
+                    account: account.clone(),
+                    signature: signature.into(),
+                    timestamp,
+                },
+            )
+        };
+
+        assert!(matches!(query(&pair, now()), Ok(Response::Post(_))));
+        let stale = now() - SIGNATURE_VALIDITY_SECS - 1;
+        assert!(matches!(query(&pair, stale), Err(Error::SignatureExpired)));
+        let other = sr25519::Pair::from_seed(&[8u8; 32]);
+        assert!(matches!(query(&other, now()), Err(Error::BadSignature)));
+    }
+
+    #[test]
+    fn access_list_is_only_visible_to_owner() {
+        use contracts::NativeContract;
+
+        let owner = AccountId::new([1u8; 32]);
+        let reader = AccountId::new([2u8; 32]);
+        let mut pastebin = Pastebin::new_dev();
+        pastebin.insert_post(
+            NewPost::new("shared".to_string(), sp_core::H256([1u8; 32]), "hello".to_string())
+                .with_readers(vec![sp_core::H256([2u8; 32])]),
+            now(),
+        );
+        let query = |pastebin: &mut Pastebin, sender: &AccountId| {
+            pastebin.handle_query(
+                Some(sender),
+                Request::QueryAccessList {
+                    id: "shared".to_string(),
+                },
+            )
+        };
+
+        assert!(pastebin.read_post(&"shared".to_string(), &reader).is_ok());
+        assert!(matches!(query(&mut pastebin, &reader), Err(Error::NotAuthorized)));
+        assert!(matches!(
+            query(&mut pastebin, &owner),
+            Ok(Response::AccessList(list)) if list == vec![reader.clone()]
+        ));
+    }
+
+    #[test]
+    fn readers_are_hidden_from_the_other_readers() {
+        use contracts::NativeContract;
+
+        let owner = AccountId::new([1u8; 32]);
+        let reader = AccountId::new([2u8; 32]);
+        let mut pastebin = Pastebin::new_dev();
+        let new_post = NewPost::new("shared".to_string(), sp_core::H256([1u8; 32]), "hello".to_string())
+            .with_readers(vec![sp_core::H256([2u8; 32]), sp_core::H256([3u8; 32])]);
+        pastebin.insert_post(new_post, now());
+        let readers_seen_by = |pastebin: &mut Pastebin, sender: &AccountId| match pastebin.handle_query(
+            Some(sender),
+            Request::QueryPost {
+                id: "shared".to_string(),
+            },
+        ) {
+            Ok(Response::Post(post)) => post.readable_by,
+            other => panic!("unexpected response: {:?}", other),
+        };
+
+        assert!(readers_seen_by(&mut pastebin, &reader).is_empty());
+        assert_eq!(readers_seen_by(&mut pastebin, &owner).len(), 2);
+        match pastebin.handle_query(
+            Some(&reader),
+            Request::QueryPosts {
+                ids: vec!["shared".to_string()],
+            },
+        ) {
+            Ok(Response::Posts(posts)) => assert!(posts[0].as_ref().unwrap().readable_by.is_empty()),
+            other => panic!("unexpected response: {:?}", other),
+        }
+        match pastebin.handle_query(
+            Some(&reader),
+            Request::QueryByTitle {
+                owner: owner.clone(),
+                title: Default::default(),
+            },
+        ) {
+            Ok(Response::Post(post)) => assert!(post.readable_by.is_empty()),
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn membership_is_checked_by_the_owner() {
+        use contracts::NativeContract;
+
+        let owner = AccountId::new([1u8; 32]);
+        let reader = AccountId::new([2u8; 32]);
+        let stranger = AccountId::new([3u8; 32]);
+        let mut pastebin = Pastebin::new_dev();
+        pastebin.insert_post(
+            NewPost::new("shared".to_string(), sp_core::H256([1u8; 32]), "hello".to_string())
+                .with_readers(vec![sp_core::H256([2u8; 32])]),
+            now(),
+        );
+        let mut can_read = |sender: &AccountId, account: &AccountId| {
+            pastebin.handle_query(
+                Some(sender),
+                Request::CanRead {
+                    id: "shared".to_string(),
+                    account: account.clone(),
+                },
+            )
+        };
+
+        assert!(matches!(can_read(&owner, &reader), Ok(Response::CanRead(true))));
+        assert!(matches!(can_read(&owner, &owner), Ok(Response::CanRead(true))));
+        assert!(matches!(can_read(&owner, &stranger), Ok(Response::CanRead(false))));
+        assert!(matches!(can_read(&reader, &stranger), Err(Error::NotAuthorized)));
+    }
+
+    #[test]
+    fn oldest_and_newest_break_ties_by_id() {
+        use contracts::NativeContract;
+
+        let owner = AccountId::new([1u8; 32]);
+        let mut pastebin = Pastebin::new_dev();
+        for (id, is_private, created_on) in
+            [("a", false, 20), ("b", false, 10), ("c", false, 20), ("d", true, 30)].iter()
+        {
+            pastebin.insert_post(new_post(id, id, *is_private), *created_on);
+        }
+        let mut query = |sender: Option<&AccountId>, req: Request| match pastebin.handle_query(sender, req) {
+            Ok(Response::PostSummary(summary)) => Some(summary.id),
+            _ => None,
+        };
+
+        assert_eq!(query(None, Request::Oldest { owner: None }).as_deref(), Some("b"));
+        assert_eq!(query(None, Request::Newest { owner: None }).as_deref(), Some("c"));
+        let mine = Some(owner.clone());
+        assert_eq!(
+            query(Some(&owner), Request::Newest { owner: mine.clone() }).as_deref(),
+            Some("d")
+        );
+        assert_eq!(query(None, Request::Newest { owner: mine }).as_deref(), Some("c"));
+        let nobody = Some(AccountId::new([2u8; 32]));
+        assert_eq!(query(None, Request::Oldest { owner: nobody }), None);
+    }
+
+    #[test]
+    fn latest_post_wins_the_title() {
+        use contracts::NativeContract;
+
+        let owner = AccountId::new([1u8; 32]);
+        let mut pastebin = Pastebin::new_dev();
+        let posts = [
+            ("b", "readme", 2),
+            ("a", "readme", 2),
+            ("c", "readme", 1),
+            ("d", "notes", 3),
+        ];
+        for &(id, title, created_on) in posts.iter() {
+            pastebin.insert_post(new_post(id, id, false).with_title(title.to_string()), created_on);
+        }
+        let mut query = |title: &str| {
+            pastebin.handle_query(
+                Some(&owner),
+                Request::QueryByTitle {
+                    owner: owner.clone(),
+                    title: title.to_string(),
+                },
+            )
+        };
+
+        assert!(matches!(query("readme"), Ok(Response::Post(post)) if post.id == "b"));
+        assert!(matches!(query("missing"), Err(Error::NotFound)));
+    }
+
+    #[test]
+    fn rename_moves_the_post() {
+        let owner = AccountId::new([1u8; 32]);
+        let mut pastebin = Pastebin::new_dev();
+        for id in ["a", "b"].iter() {
+            pastebin.insert_post(new_post(id, id, false), now());
+        }
+        let other = AccountId::new([2u8; 32]);
+        let rename = |pastebin: &mut Pastebin, sender: &AccountId, old_id: &str, new_id: &str| {
+            pastebin.rename_post(sender, &old_id.to_string(), new_id.to_string())
+        };
+
+        assert!(matches!(
+            rename(&mut pastebin, &other, "a", "c"),
+            Err(TransactionError::Unauthorized)
+        ));
+        assert!(matches!(
+            rename(&mut pastebin, &owner, "a", "b"),
+            Err(TransactionError::IdExists)
+        ));
+        assert!(matches!(
+            rename(&mut pastebin, &owner, "x", "c"),
+            Err(TransactionError::NotFound)
+        ));
+        assert!(rename(&mut pastebin, &owner, "a", "c").is_ok());
+        assert!(!pastebin.post_by_id.contains_key("a"));
+        let post = pastebin.post_by_id.get("c").unwrap();
+        assert_eq!(post.id, "c");
+        assert_eq!(post.content(), "a");
+    }
+
+    #[test]
+    fn clone_copies_the_post_to_the_sender() {
+        let owner = AccountId::new([1u8; 32]);
+        let reader = AccountId::new([2u8; 32]);
+        let other = AccountId::new([3u8; 32]);
+        let mut pastebin = Pastebin::new_dev();
+        pastebin.insert_post(
+            NewPost::new("a".to_string(), sp_core::H256([1u8; 32]), "content".to_string())
+                .with_readers(vec![sp_core::H256([2u8; 32])])
+                .with_title("title".to_string())
+                .with_metadata("tag".to_string(), "rust".to_string()),
+            100,
+        );
+        let clone = |pastebin: &mut Pastebin, sender: &AccountId, source_id: &str, new_id: &str| {
+            pastebin.clone_post(sender, &source_id.to_string(), new_id.to_string(), 200)
+        };
+
+        assert!(matches!(
+            clone(&mut pastebin, &other, "a", "b"),
+            Err(TransactionError::Unauthorized)
+        ));
+        assert!(matches!(
+            clone(&mut pastebin, &reader, "a", "a"),
+            Err(TransactionError::IdExists)
+        ));
+        assert!(matches!(
+            clone(&mut pastebin, &reader, "x", "b"),
+            Err(TransactionError::NotFound)
+        ));
+        assert!(clone(&mut pastebin, &reader, "a", "b").is_ok());
+        let post = pastebin.post_by_id.get("b").unwrap();
+        assert_eq!(post.owner, reader);
+        assert_eq!(post.created_on, 200);
+        assert_eq!(post.updated_on, 200);
+        assert!(post.readable_by.is_empty());
+        assert_eq!(post.title, "title");
+        assert_eq!(post.content(), "content");
+        assert_eq!(post.metadata.get("tag").map(String::as_str), Some("rust"));
+        assert_eq!(pastebin.post_count_of(&reader), 1);
+        assert_eq!(pastebin.post_by_id.get("a").unwrap().owner, owner);
+    }
+
+    #[test]
+    fn share_token_reads_the_private_post() {
+        use contracts::NativeContract;
+        let owner = AccountId::new([1u8; 32]);
+        let other = AccountId::new([2u8; 32]);
+        let mut pastebin = Pastebin::new_dev();
+        for (id, is_private) in [("private", true), ("public", false)].iter() {
+            pastebin.insert_post(new_post(id, id, *is_private), now());
+        }
+        let id = "private".to_string();
+        let token = [7u8; 32];
+        let query = |pastebin: &mut Pastebin, token: ShareToken| {
+            pastebin.handle_query(None, Request::QueryPostByToken { token })
+        };
+
+        assert!(matches!(
+            pastebin.set_share_token(&other, &id, Some(token)),
+            Err(TransactionError::Unauthorized)
+        ));
+        assert!(matches!(
+            pastebin.set_share_token(&owner, &"public".to_string(), Some(token)),
+            Err(TransactionError::BadInput)
+        ));
+        assert!(matches!(query(&mut pastebin, token), Err(Error::NotFound)));
+        assert!(pastebin.set_share_token(&owner, &id, Some(token)).is_ok());
+        assert!(matches!(query(&mut pastebin, token), Ok(Response::Post(post)) if post.id == "private"));
+        assert_ne!(
+            pastebin.share_token_seed(&id),
+            pastebin.share_token_seed(&"public".to_string())
+        );
+
+        // the token follows the post and a new one replaces it
+        assert!(pastebin.rename_post(&owner, &id, "renamed".to_string()).is_ok());
+        assert!(matches!(query(&mut pastebin, token), Ok(Response::Post(post)) if post.id == "renamed"));
+        let id = "renamed".to_string();
+        assert!(pastebin.set_share_token(&owner, &id, Some([8u8; 32])).is_ok());
+        assert!(matches!(query(&mut pastebin, token), Err(Error::NotFound)));
+        assert!(matches!(
+            pastebin.handle_query(Some(&other), Request::QueryShareToken { id: id.clone() }),
+            Err(Error::NotAuthorized)
+        ));
+        assert!(matches!(
+            pastebin.handle_query(Some(&owner), Request::QueryShareToken { id: id.clone() }),
+            Ok(Response::ShareToken(Some(token))) if token == [8u8; 32]
+        ));
+
+        assert!(pastebin.set_share_token(&owner, &id, None).is_ok());
+        assert!(matches!(query(&mut pastebin, [8u8; 32]), Err(Error::NotFound)));
+        assert!(matches!(
+            pastebin.set_share_token(&owner, &id, None),
+            Err(TransactionError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn chunks_join_into_the_content() {
+        use contracts::NativeContract;
+        let owner = AccountId::new([1u8; 32]);
+        let mut pastebin = Pastebin::new_dev();
+        let content = "é".repeat(5000);
+        pastebin.insert_post(
+            NewPost::new("large".to_string(), sp_core::H256([1u8; 32]), content.clone()).with_readers(vec![]),
+            now(),
+        );
+        let mut query = |sender: &AccountId, chunk_index: u32, chunk_size: u32| {
+            pastebin.handle_query(
+                Some(sender),
+                Request::QueryPostChunk {
+                    id: "large".to_string(),
+                    chunk_index,
+                    chunk_size,
+                },
+            )
+        };
+
+        assert!(matches!(query(&owner, 0, 0), Err(Error::InvalidChunkSize)));
+        assert!(matches!(
+            query(&owner, 0, MAX_CHUNK_BYTES + 1),
+            Err(Error::InvalidChunkSize)
+        ));
+        assert!(matches!(
+            query(&AccountId::new([2u8; 32]), 0, 1000),
+            Err(Error::NotAuthorized)
+        ));
+        let mut joined = vec![];
+        for chunk_index in 0.. {
+            match query(&owner, chunk_index, 3001) {
+                Ok(Response::Chunk { data, is_last }) => {
+                    joined.extend(data);
+                    if is_last {
+                        break;
+                    }
+                }
+                other => panic!("unexpected response: {:?}", other),
+            }
+        }
+        assert_eq!(String::from_utf8(joined).unwrap(), content);
+        assert!(matches!(
+            query(&owner, u32::MAX, MAX_CHUNK_BYTES),
+            Ok(Response::Chunk { data, is_last: true }) if data.is_empty()
+        ));
+    }
+
+    #[test]
+    fn encrypted_content_is_checked_against_the_algorithm() {
+        use phala_types::messaging::EncryptionAlgorithm;
+
+        let pastebin = Pastebin::new_dev();
+        let meta = |algorithm: EncryptionAlgorithm, nonce_len: usize| EncryptionMeta {
+            algorithm,
+            nonce: vec![0u8; nonce_len],
+            recipient_hint: None,
+        };
+        let ciphertext = base64::encode([42u8; 32]);
+        let validate = |encryption: &EncryptionMeta, content: &str| {
+            pastebin.validate_encryption(Some(encryption), &content.to_string())
+        };
+
+        assert!(validate(&meta(EncryptionAlgorithm::Aes256Gcm, 12), &ciphertext).is_ok());
+        assert!(validate(&meta(EncryptionAlgorithm::XChaCha20Poly1305, 24), &ciphertext).is_ok());
+        assert!(matches!(
+            validate(&meta(EncryptionAlgorithm::XChaCha20Poly1305, 12), &ciphertext),
+            Err(TransactionError::InvalidEncryption)
+        ));
+        assert!(matches!(
+            validate(&meta(EncryptionAlgorithm::Aes256Gcm, 12), "# plain markdown"),
+            Err(TransactionError::InvalidEncryption)
+        ));
+        // too short to hold the authentication tag
+        assert!(matches!(
+            validate(&meta(EncryptionAlgorithm::Aes256Gcm, 12), &base64::encode([42u8; 8])),
+            Err(TransactionError::InvalidEncryption)
+        ));
+        assert!(pastebin
+            .validate_encryption(None, &"# plain markdown".to_string())
+            .is_ok());
+    }
+
+    #[test]
+    fn exported_posts_are_imported_back() {
+        use contracts::NativeContract;
+
+        let owner = AccountId::new([1u8; 32]);
+        let mut pastebin = Pastebin::new_dev();
+        for id in ["a", "b"].iter() {
+            pastebin.insert_post(new_post(id, &id.repeat(COMPRESSION_THRESHOLD * 2), true), now());
+        }
+        let posts = match pastebin.handle_query(
+            Some(&owner),
+            Request::ExportPosts {
+                owner: owner.clone(),
+                cursor: None,
+            },
+        ) {
+            Ok(Response::Export {
+                posts,
+                next_cursor: None,
+            }) => posts,
+            other => panic!("unexpected export: {:?}", other),
+        };
+        let encoded: Vec<Vec<u8>> = posts.iter().map(Encode::encode).collect();
+
+        let mut restored = Pastebin::new_dev();
+        assert!(matches!(
+            restored.import_posts(&AccountId::new([2u8; 32]), encoded.clone(), false, now()),
+            Err(TransactionError::BatchItemRejected(0))
+        ));
+        assert!(restored.import_posts(&owner, encoded.clone(), false, now()).is_ok());
+        assert_eq!(restored.post_count_of(&owner), 2);
+        assert_eq!(
+            restored.post_by_id.get("b").unwrap().content(),
+            "b".repeat(COMPRESSION_THRESHOLD * 2)
+        );
+        assert!(matches!(
+            restored.import_posts(&owner, encoded.clone(), false, now()),
+            Err(TransactionError::BatchItemRejected(0))
+        ));
+        assert!(restored.import_posts(&owner, encoded, true, now()).is_ok());
+        assert_eq!(restored.post_count_of(&owner), 2);
+    }
+
+    #[test]
+    fn deleted_posts_are_restorable_until_purged() {
+        let owner = AccountId::new([1u8; 32]);
+        let mut pastebin = Pastebin::new_dev();
+        for id in ["a", "b"].iter() {
+            pastebin.insert_post(new_post(id, id, false), 0);
+        }
+        let (a, b) = ("a".to_string(), "b".to_string());
+
+        assert!(pastebin.set_deleted(&owner, &a, Some(100)).is_ok());
+        assert!(matches!(pastebin.read_post(&a, &owner), Err(Error::NotFound)));
+        assert!(matches!(
+            pastebin.set_deleted(&AccountId::new([2u8; 32]), &a, None),
+            Err(TransactionError::Unauthorized)
+        ));
+        assert!(pastebin.set_deleted(&owner, &a, None).is_ok());
+        assert!(pastebin.read_post(&a, &owner).is_ok());
+        assert!(matches!(
+            pastebin.set_deleted(&owner, &a, None),
+            Err(TransactionError::NotFound)
+        ));
+
+        assert!(pastebin.set_deleted(&owner, &b, Some(200)).is_ok());
+        pastebin.purge_trash(200 + TRASH_RETENTION_SECS - 1);
+        assert!(pastebin.post_by_id.contains_key(&b));
+        pastebin.purge_trash(200 + TRASH_RETENTION_SECS);
+        assert!(!pastebin.post_by_id.contains_key(&b));
+        assert!(pastebin.trash.is_empty());
+        assert_eq!(pastebin.post_count_of(&owner), 1);
+    }
+
+    #[test]
+    fn stats_count_only_the_live_posts() {
+        use contracts::NativeContract;
+        let owner = AccountId::new([1u8; 32]);
+        let mut pastebin = with_an_exhausted_post();
+        pastebin.set_deleted(&owner, &"reply".to_string(), Some(now())).unwrap();
+        assert!(matches!(
+            pastebin.handle_query(Some(&owner), Request::QueryStats),
+            Ok(Response::Stats {
+                total_posts: 1,
+                owner_posts: 3,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn batch_query_has_a_result_per_id() {
+        use contracts::NativeContract;
+        let owner = AccountId::new([1u8; 32]);
+        let mut pastebin = Pastebin::new_dev();
+        for (id, is_private) in [("public", false), ("private", true)].iter() {
+            pastebin.insert_post(new_post(id, id, *is_private), now());
+        }
+        let ids = ["public", "private", "missing"]
+            .iter()
+            .map(|id| id.to_string())
+            .collect();
+        let results = match pastebin.handle_query(Some(&AccountId::new([2u8; 32])), Request::QueryPosts { ids }) {
+            Ok(Response::Posts(results)) => results,
+            other => panic!("unexpected response: {:?}", other),
+        };
+        assert!(matches!(&results[0], Ok(post) if post.id == "public"));
+        assert!(matches!(results[1], Err(Error::NotAuthorized)));
+        assert!(matches!(results[2], Err(Error::NotFound)));
+
+        let ids = vec!["public".to_string(); MAX_PAGE_SIZE as usize + 1];
+        assert!(matches!(
+            pastebin.handle_query(Some(&owner), Request::QueryPosts { ids }),
+            Err(Error::BatchTooLarge)
+        ));
+    }
+
+    #[test]
+    fn replies_are_listed_under_their_parent() {
+        use contracts::NativeContract;
+        let owner = AccountId::new([1u8; 32]);
+        let stranger = AccountId::new([2u8; 32]);
+        let mut pastebin = Pastebin::new_dev();
+        for (id, is_private, parent_id) in [
+            ("root", false, None),
+            ("second", false, Some("root")),
+            ("first", false, Some("root")),
+            ("private", true, Some("root")),
+            ("nested", false, Some("first")),
+        ]
+        .iter()
+        {
+            pastebin.insert_post(
+                match parent_id {
+                    Some(parent_id) => new_post(id, id, *is_private).with_parent(parent_id.to_string()),
+                    None => new_post(id, id, *is_private),
+                },
+                100,
+            );
+        }
+        // an imported post may point to a parent which doesn't exist yet
+        pastebin.post_by_id.get_mut("root").unwrap().parent_id = Some("later".to_string());
+
+        let check = |id: &str, owner: &AccountId, parent_id: &str| {
+            pastebin.validate_parent(&id.to_string(), owner, Some(&parent_id.to_string()), 100)
+        };
+        assert!(matches!(
+            check("later", &owner, "nested"),
+            Err(TransactionError::BadInput)
+        ));
+        assert!(check("reply", &owner, "nested").is_ok());
+        assert!(matches!(
+            check("reply", &owner, "missing"),
+            Err(TransactionError::NotFound)
+        ));
+        assert!(matches!(
+            check("reply", &stranger, "private"),
+            Err(TransactionError::Unauthorized)
+        ));
+
+        let mut replies = |sender: &AccountId| match pastebin.handle_query(
+            Some(sender),
+            Request::QueryReplies {
+                id: "root".to_string(),
+                offset: 0,
+                limit: 10,
+            },
+        ) {
+            Ok(Response::PostList(list)) => list.into_iter().map(|post| post.id).collect::<Vec<_>>(),
+            other => panic!("unexpected response: {:?}", other),
+        };
+        assert_eq!(replies(&owner), vec!["first", "private", "second"]);
+        assert_eq!(replies(&stranger), vec!["first", "second"]);
+    }
+
+    #[test]
+    fn audit_log_is_bounded_and_root_only() {
+        use contracts::NativeContract;
+        let mut pastebin = Pastebin::new_dev();
+        let root = pastebin.root.clone();
+        for block in 0..contracts::MAX_AUDIT_ENTRIES as u32 + 2 {
+            contracts::record_audit(
+                &mut pastebin.audit_log,
+                AuditEntry {
+                    block,
+                    sender: root.clone(),
+                    action: "SetPostQuota".to_string(),
+                },
+            );
+        }
+        assert_eq!(pastebin.audit_log.len(), contracts::MAX_AUDIT_ENTRIES);
+        assert_eq!(pastebin.audit_log[0].block, 2);
+
+        let page = match pastebin.handle_query(Some(&root), Request::QueryAuditLog { offset: 1, limit: 2 }) {
+            Ok(Response::AuditLog(page)) => page,
+            other => panic!("unexpected response: {:?}", other),
+        };
+        assert_eq!(page.iter().map(|entry| entry.block).collect::<Vec<_>>(), vec![3, 4]);
+        assert!(matches!(
+            pastebin.handle_query(
+                Some(&AccountId::new([2u8; 32])),
+                Request::QueryAuditLog { offset: 0, limit: 2 }
+            ),
+            Err(Error::NotAuthorized)
+        ));
+        assert_eq!(
+            audit_action(&Command::SetPostQuota { max_posts_per_owner: 1 }),
+            Some("SetPostQuota")
+        );
+        assert_eq!(audit_action(&Command::DeletePost { id: "post".to_string() }), None);
+    }
+
+    #[test]
+    fn access_is_granted_to_the_posts_of_the_sender_only() {
+        let owner = AccountId::new([1u8; 32]);
+        let reader = AccountId::new([2u8; 32]);
+        let mut pastebin = Pastebin::new_dev();
+        let posts = [
+            ("mine", [1u8; 32]),
+            ("shared", [1u8; 32]),
+            ("theirs", [3u8; 32]),
+            ("public", [1u8; 32]),
+        ];
+        for (id, owner) in posts.iter() {
+            pastebin.insert_post(
+                match (*id, NewPost::new(id.to_string(), sp_core::H256(*owner), id.to_string())) {
+                    ("public", new_post) => new_post,
+                    ("shared", new_post) => new_post.with_readers(vec![sp_core::H256([2u8; 32])]),
+                    (_, new_post) => new_post.with_readers(vec![]),
+                },
+                now(),
+            );
+        }
+        let ids: Vec<PostId> = ["mine", "shared", "theirs", "missing", "public", "mine"]
+            .iter()
+            .map(|id| id.to_string())
+            .collect();
+        assert_eq!(pastebin.grant_access_bulk(&owner, &ids, &reader).unwrap(), 1);
+        assert!(pastebin.post_by_id["public"].readable_by.is_empty());
+        assert_eq!(pastebin.post_by_id["mine"].readable_by, vec![reader.clone()]);
+        assert_eq!(pastebin.post_by_id["shared"].readable_by, vec![reader.clone()]);
+        assert!(pastebin.post_by_id["theirs"].readable_by.is_empty());
+
+        let ids = vec!["mine".to_string(); MAX_BATCH_SIZE + 1];
+        assert!(matches!(
+            pastebin.grant_access_bulk(&owner, &ids, &reader),
+            Err(TransactionError::BatchTooLarge)
+        ));
+    }
+
+    #[test]
+    fn expired_posts_are_hidden_or_removed() {
+        let owner = AccountId::new([1u8; 32]);
+        let mut pastebin = Pastebin::new_dev();
+        for id in ["hidden", "removed", "renamed"].iter() {
+            pastebin.insert_post(new_post(id, id, false).with_expiry(100), 0);
+        }
+        let set = |pastebin: &mut Pastebin, sender: &AccountId, id: &str| {
+            pastebin.set_expiry_action(sender, &id.to_string(), ExpiryAction::Delete)
+        };
+
+        assert!(matches!(
+            set(&mut pastebin, &AccountId::new([2u8; 32]), "removed"),
+            Err(TransactionError::Unauthorized)
+        ));
+        assert!(set(&mut pastebin, &owner, "removed").is_ok());
+        assert!(set(&mut pastebin, &owner, "renamed").is_ok());
+        assert!(pastebin
+            .rename_post(&owner, &"renamed".to_string(), "moved".to_string())
+            .is_ok());
+
+        pastebin.sweep_expired(99);
+        assert_eq!(pastebin.post_by_id.len(), 3);
+        pastebin.sweep_expired(100);
+        assert!(pastebin.post_by_id.contains_key("hidden"));
+        assert!(!pastebin.post_by_id.contains_key("removed"));
+        assert!(!pastebin.post_by_id.contains_key("moved"));
+        assert!(pastebin.expiring.is_empty());
+        assert_eq!(pastebin.post_count_of(&owner), 1);
+    }
+
+    #[test]
+    fn lifetimes_are_capped_by_max_ttl() {
+        let mut pastebin = Pastebin::new_dev();
+        pastebin.default_ttl = Some(50);
+        assert_eq!(pastebin.effective_expiry(None, 1000), Some(1050));
+        assert_eq!(pastebin.effective_expiry(Some(2000), 1000), Some(2000));
+
+        pastebin.max_ttl = Some(100);
+        assert_eq!(pastebin.effective_expiry(None, 1000), Some(1050));
+        assert_eq!(pastebin.effective_expiry(Some(2000), 1000), Some(1100));
+        assert!(pastebin.validate_expiry(Some(2000), 1000).is_ok());
+        pastebin.default_ttl = None;
+        assert_eq!(pastebin.effective_expiry(None, 1000), Some(1100));
+
+        pastebin.reject_longer_ttl = true;
+        assert!(matches!(
+            pastebin.validate_expiry(Some(1101), 1000),
+            Err(TransactionError::TtlTooLong)
+        ));
+        assert!(pastebin.validate_expiry(Some(1100), 1000).is_ok());
+        assert!(pastebin.validate_expiry(None, 1000).is_ok());
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let config = PastebinConfig::default();
+        let mut post = post_with_content("v0");
+        for i in 1..=MAX_HISTORY_ENTRIES + 2 {
+            post.edit(format!("v{}", i), i as u64, &config);
+        }
+        assert_eq!(post.history.len(), MAX_HISTORY_ENTRIES);
+        assert_eq!(post.history[0], (2, "v2".to_string()));
+        assert_eq!(post.content(), format!("v{}", MAX_HISTORY_ENTRIES + 2));
+
+        let big = "a".repeat(MAX_HISTORY_BYTES / 2 + 1);
+        post.edit(big.clone(), 100, &config);
+        post.edit(big.clone(), 101, &config);
+        post.edit("small".to_string(), 102, &config);
+        // only one of the big contents fits in the history
+        assert_eq!(post.history.last(), Some(&(101, big)));
+        assert!(post.history.iter().map(|(_, old)| old.len()).sum::<usize>() <= MAX_HISTORY_BYTES);
+    }
+
+    #[test]
+    fn diff_lists_the_changed_lines_between_versions() {
+        let config = PastebinConfig::default();
+        let mut post = post_with_content("a\nb\nc\nd");
+        post.edit("a\nB\nc\nd\ne".to_string(), 1, &config);
+        assert_eq!(post.content_at(0).unwrap(), "a\nb\nc\nd");
+        assert_eq!(post.content_at(1).unwrap(), post.content());
+        assert!(post.content_at(2).is_none());
+
+        let (lines, truncated) = line_diff(&post.content_at(0).unwrap(), &post.content(), MAX_DIFF_LINES);
+        assert!(!truncated);
+        assert_eq!(
+            lines,
+            vec![
+                DiffLine::Removed {
+                    line: 1,
+                    text: "b".to_string()
+                },
+                DiffLine::Added {
+                    line: 1,
+                    text: "B".to_string()
+                },
+                DiffLine::Added {
+                    line: 4,
+                    text: "e".to_string()
+                },
+            ]
+        );
+        assert!(line_diff("same", "same", MAX_DIFF_LINES).0.is_empty());
+
+        let (lines, truncated) = line_diff("", "1\n2\n3", 2);
+        assert!(truncated);
+        assert_eq!(lines.len(), 2);
+    }
+
+    #[test]
+    fn timestamps_are_formatted_in_iso_8601() {
+        assert_eq!(format_timestamp(0, 0).unwrap(), "1970-01-01T00:00:00+00:00");
+        assert_eq!(format_timestamp(1_634_284_800, 0).unwrap(), "2021-10-15T08:00:00+00:00");
+        assert_eq!(
+            format_timestamp(1_634_284_800, 8 * 60).unwrap(),
+            "2021-10-15T16:00:00+08:00"
+        );
+        assert_eq!(
+            format_timestamp(1_634_284_800, -(5 * 60 + 30)).unwrap(),
+            "2021-10-15T02:30:00-05:30"
+        );
+        assert!(matches!(format_timestamp(0, 24 * 60), Err(Error::InvalidUtcOffset)));
+        assert!(matches!(format_timestamp(u64::MAX, 0), Err(Error::DateOutOfRange)));
     }
 }
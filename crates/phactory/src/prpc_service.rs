@@ -343,10 +343,12 @@ impl<Platform: pal::Platform> Phactory<Platform> {
                             .into(),
                         contract_key,
                     );
+                    let outcome_mq = recv_mq.subscribe(contract::outcome_topic(contract_id));
                     let wrapped = Box::new(contracts::NativeCompatContract::new(
                         $inner,
                         mq,
                         cmd_mq,
+                        outcome_mq,
                         ecdh_key.clone(),
                     ));
                     contracts.insert(contract_id, wrapped);
@@ -386,10 +388,13 @@ impl<Platform: pal::Platform> Phactory<Platform> {
 
             install_contract!(
                 contracts::BTC_PRICE_BOT,
-                contracts::btc_price_bot::BtcPriceBot::new()
+                contracts::btc_price_bot::BtcPriceBot::new_dev()
             );
 
-            install_contract!(contracts::PASTEBIN, contracts::pastebin::Pastebin::new());
+            install_contract!(
+                contracts::PASTEBIN,
+                contracts::pastebin::Pastebin::new_dev()
+            );
         }
 
         let mut runtime_state = RuntimeState {
@@ -589,7 +594,7 @@ impl<Platform: pal::Platform> Phactory<Platform> {
                 .contracts
                 .get_mut(&head.id)
                 .ok_or_else(|| from_display("Contract not found"))?;
-            contract.handle_query(ref_origin, data_cursor)?
+            contract.handle_query(&state.chain_storage, ref_origin, data_cursor)?
         };
 
         // Encode response
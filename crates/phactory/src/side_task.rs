@@ -100,6 +100,7 @@ pub mod async_side_task {
     use chain::BlockNumber;
     use futures::Future;
     use std::sync::{Arc, Mutex};
+    use std::time::Duration;
 
     use crate::side_task::{PollContext, PollState, SideTask};
 
@@ -178,4 +179,54 @@ pub mod async_side_task {
             }
         }
     }
+
+    /// Retry an async operation with exponential backoff.
+    ///
+    /// `op` is tried at most `max_attempts` times. The n-th retry waits `base_delay * 2^(n-1)`, capped at `max_delay`.
+    /// Retrying stops early once the total waiting time would exceed `window`, so that the retries are done before
+    /// the side task reports its result.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let body = retry_with_backoff(3, Duration::from_millis(500), Duration::from_secs(2), Duration::from_secs(6), || async {
+    ///     surf::get("https://ifconfig.me").recv_string().await
+    /// })
+    /// .await;
+    /// ```
+    pub async fn retry_with_backoff<T, E, F, Fut>(
+        max_attempts: u32,
+        base_delay: Duration,
+        max_delay: Duration,
+        window: Duration,
+        mut op: F,
+    ) -> Result<T, E>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut waited = Duration::from_secs(0);
+        let mut attempt = 1;
+        loop {
+            let err = match op().await {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            let delay = backoff_delay(base_delay, max_delay, attempt);
+            if attempt >= max_attempts || waited + delay > window {
+                return Err(err);
+            }
+            async_io::Timer::after(delay).await;
+            waited += delay;
+            attempt += 1;
+        }
+    }
+
+    /// The delay before the `retry`-th retry, counting from 1
+    fn backoff_delay(base_delay: Duration, max_delay: Duration, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        base_delay
+            .checked_mul(factor)
+            .map_or(max_delay, |delay| delay.min(max_delay))
+    }
 }
@@ -36,7 +36,7 @@ impl BlockValidator for LightValidation<chain::Runtime> {
 
 mod storage_ext {
     use crate::chain;
-    use crate::light_validation::utils::storage_prefix;
+    use crate::light_validation::utils::{storage_map_prefix_blake2_128_concat, storage_prefix};
     use phactory_api::blocks::ParaId;
     use log::error;
     use parity_scale_codec::{Decode, Error};
@@ -76,6 +76,17 @@ mod storage_ext {
         fn timestamp_now(&self) -> Option<chain::Moment> {
             self.get_decoded(storage_prefix("Timestamp", "Now"))
         }
+        /// The free balance of `account`, zero if it has no account on chain
+        fn free_balance(&self, account: &chain::AccountId) -> chain::Balance {
+            type AccountInfo = frame_system::AccountInfo<
+                <chain::Runtime as frame_system::Config>::Index,
+                <chain::Runtime as frame_system::Config>::AccountData,
+            >;
+            let key = storage_map_prefix_blake2_128_concat(b"System", b"Account", account);
+            self.get_decoded::<AccountInfo>(key)
+                .map(|info| info.data.free)
+                .unwrap_or_default()
+        }
     }
 
     impl StorageExt for Storage {
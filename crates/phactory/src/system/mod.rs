@@ -57,8 +57,42 @@ pub enum TransactionError {
     FailedToCalculateBalance,
     BadChainId,
     TransferringNotAllowed,
+    // for native contracts
+    NotFound,
+    Unauthorized,
+    RateLimited,
+    NotConfigured,
+    Paused,
+    /// The URL is malformed or not in the allowlist
+    InvalidUrl,
+    /// The side tasks are suspended after too many consecutive failures
+    CircuitOpen,
+    /// Too many side tasks of the contract are in flight to spawn another one
+    Busy,
     // for pastebin
     IdExists,
+    /// The content is larger than the limit in bytes it carries
+    ContentTooLarge(u32),
+    QuotaExceeded,
+    BatchTooLarge,
+    /// The item at the given index of a batch is invalid, so the whole batch is rejected
+    BatchItemRejected(u32),
+    MetadataTooLarge,
+    TooManyReaders,
+    /// The nonce or the ciphertext doesn't fit the declared encryption
+    InvalidEncryption,
+    /// The content of an encrypted post can't be changed
+    ContentEncrypted,
+    /// The expiry is later than the maximum lifetime allows
+    TtlTooLong,
+    /// A public post contains a word blocked by the root account
+    Blocked,
+    /// The Command needs unguessable randomness but the block carries no on-chain entropy yet
+    EntropyUnavailable,
+    /// The Command would overwrite settings already made, which needs the Command meant for replacing them
+    AlreadyConfigured,
+    /// An input doesn't follow the format rules of the contract, e.g. the charset of a post id
+    InvalidInput,
 }
 
 impl From<BadOrigin> for TransactionError {
@@ -67,6 +101,29 @@ impl From<BadOrigin> for TransactionError {
     }
 }
 
+impl TransactionError {
+    /// A stable HTTP-like status code of the error, carried by the `CommandAck` of a failed Command
+    pub fn code(&self) -> u16 {
+        use TransactionError::*;
+        match self {
+            BadInput | BadContractId | BadCommand | BadSecret | BadMachineId | BadDecimal | BadAccountInfo
+            | BadLedgerInfo | BadTrustedStateData | BadEpochChangedProofData | BadTrustedState | InvalidAccount
+            | BadTransactionWithProof | BadChainId | InvalidUrl | InvalidInput => 400,
+            BadOrigin | FailedToVerify => 401,
+            InsufficientBalance | NoBalance => 402,
+            Unauthorized | NotAssetOwner | DestroyNotAllowed | TransferringNotAllowed | QuotaExceeded => 403,
+            NotFound | AssetIdNotFound | FailedToGetTransaction => 404,
+            SymbolExist | IdExists | ContentEncrypted | AlreadyConfigured => 409,
+            NotConfigured => 412,
+            ContentTooLarge(_) | BatchTooLarge | MetadataTooLarge | TooManyReaders => 413,
+            BatchItemRejected(_) | InvalidEncryption | TtlTooLong | Blocked => 422,
+            RateLimited => 429,
+            UnknownError | FailedToSign | FailedToCalculateBalance => 500,
+            Paused | CircuitOpen | Busy | EntropyUnavailable => 503,
+        }
+    }
+}
+
 #[derive(Debug)]
 struct BenchState {
     start_block: chain::BlockNumber,
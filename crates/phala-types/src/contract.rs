@@ -80,3 +80,10 @@ pub fn command_topic(id: ContractId) -> Vec<u8> {
         .as_bytes()
         .to_vec()
 }
+
+/// The topic the outcomes of the side tasks of a contract are sent to, see `OutcomeReporter` in phactory
+pub fn outcome_topic(id: ContractId) -> Vec<u8> {
+    format!("phala/contract/{}/outcome", hex::encode(&id))
+        .as_bytes()
+        .to_vec()
+}
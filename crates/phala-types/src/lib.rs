@@ -12,6 +12,7 @@ use sp_core::H256;
 // Messages: Phase Wallet
 
 pub mod messaging {
+    use alloc::collections::BTreeMap;
     use alloc::string::String;
     use alloc::vec::Vec;
     use codec::{Decode, Encode};
@@ -253,9 +254,306 @@ pub mod messaging {
         SetOwner { owner: AccountId },
         /// Set the authentication token of telegram bot (https://core.telegram.org/bots/api#authorizing-your-bot) and
         /// the identifier to target chat (https://core.telegram.org/bots/api#sendmessage)
+        ///
+        /// It fails with `AlreadyConfigured` if another bot is already set, which must be replaced with `UpdateBot`.
         SetupBot { token: String, chat_id: String },
         /// Let the Tg bot to report the current BTC price
         ReportBtcPrice,
+        /// Send the reports to a Slack incoming webhook (https://api.slack.com/messaging/webhooks) instead of Telegram
+        SetupSlack { webhook_url: String },
+        /// Set the report message template, where `{symbol}`, `{price}` and `{to}` are substituted, and the number of
+        /// decimals the price is rounded to
+        SetMessageFormat { template: String, decimals: u8 },
+        /// Send a test message through the configured notifier, the result can be checked with `QueryLastError`
+        TestNotification,
+        /// Get the price from `url` instead, where `price_path` is the JSON pointer (e.g. `/data/amount`) to the price
+        /// in its response
+        ///
+        /// `{symbol}` in both of them is replaced with the symbol of the reported pairs, `{tsyms}` in `url` with the
+        /// comma separated currencies they are priced in and `{to}` in `price_path` with each of the currencies.
+        SetPriceSource { url: String, price_path: String },
+        /// Pause or resume the contract, a paused contract rejects all the other Commands
+        SetPaused { paused: bool },
+        /// Set the URL prefixes the bot is allowed to request, only allowed for the root account
+        SetAllowedHosts { hosts: Vec<String> },
+        /// Set the texts added before and after every notification, e.g. `[Mainnet] ` and ` #oracle`
+        SetMessageAffixes { prefix: String, suffix: String },
+        /// Set the minimum number of blocks between two `ReportBtcPrice`
+        SetReportInterval { blocks: u32 },
+        /// Set the (symbol, currency) pairs covered by each report, e.g. `[("BTC", "USD"), ("BTC", "EUR")]`
+        SetPricePairs { pairs: Vec<(String, String)> },
+        /// Set the User-Agent header of the HTTP requests sent by the bot
+        SetUserAgent { user_agent: String },
+        /// Set the secret to sign the webhook requests with, `None` to send them unsigned
+        ///
+        /// The requests carry the hex HMAC-SHA256 of their body in the `X-Phala-Signature-256` header, as
+        /// `sha256=<hex>`, so that the receivers can reject the spoofed ones.
+        SetWebhookSecret { secret: Option<String> },
+        /// Forget the failed deliveries recorded for the `QueryFailedDeliveries` Query
+        ClearFailedDeliveries,
+        /// Reset the counters of the `QueryMetrics` Query, only allowed for the root account
+        ResetMetrics,
+        /// Hand the contract over to `new_owner`, only allowed for the owner or the root account
+        ///
+        /// Unlike `SetOwner`, the current owner can pass it on by itself, e.g. to rotate its key. The transfer is
+        /// announced with an `OwnerTransferred` event.
+        TransferContractOwner { new_owner: AccountId },
+        /// Compose the reports without sending them, to check the settings without notifying the chat
+        SetDryRun { dry_run: bool },
+        /// Set how the prices are written in the messages: the separator between the groups of three digits, and the
+        /// symbol put before them, e.g. `$42,000.50`
+        SetPriceFormat {
+            grouping: DigitGrouping,
+            currency_symbol: String,
+        },
+        /// Send the requests of the side tasks through the HTTP proxy at `proxy_url`, `None` to connect directly, only
+        /// allowed for the root account
+        ///
+        /// The proxy must be a plain `http://host:port` URL without credentials, the requests are tunneled through it
+        /// with `CONNECT`.
+        SetProxy { proxy_url: Option<String> },
+        /// Alert the Telegram chat `chat_id` through the bot set by `SetupBot` when a report fails, `None` to stop the
+        /// alerts
+        ///
+        /// The alerts carry the error of the report, with the bot token and the webhook settings redacted.
+        SetErrorChat { chat_id: Option<String> },
+        /// Replace the Telegram bot token and chat id set by `SetupBot`
+        UpdateBot { token: String, chat_id: String },
+        /// Replace only the Telegram bot token set by `SetupBot`, keeping the chat ids and the other settings, e.g. to
+        /// rotate a leaked or expiring token
+        ///
+        /// With `send_test`, a `TestNotification` is sent with the new token to check it works, and the rotation is
+        /// undone if the test can't be started.
+        RotateBotToken { new_token: String, send_test: bool },
+    }
+
+    /// The separator between the groups of three digits of the prices in the BtcPriceBot messages
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+    pub enum DigitGrouping {
+        /// `42000.50`
+        Ungrouped,
+        /// `42,000.50`
+        Comma,
+        /// `42 000.50`
+        Space,
+    }
+
+    bind_topic!(BtcPriceBotEvent, b"phala/btc_price_bot/event");
+    /// The events of the BtcPriceBot
+    #[derive(Debug, Clone, Encode, Decode)]
+    pub enum BtcPriceBotEvent {
+        /// The contract owner is changed by `TransferContractOwner`
+        OwnerTransferred { from: AccountId, to: AccountId },
+    }
+
+    /// The outcomes of the BtcPriceBot side tasks, sent to the `contract::outcome_topic` of the contract
+    ///
+    /// Exactly one outcome is sent per side task in its result process, whether the task succeeded or not, so that
+    /// all the replicas send the same number of messages. Each replica reports the result it got, the chain only
+    /// accepts the first message of each sequence number.
+    #[derive(Debug, Clone, Encode, Decode)]
+    pub enum BtcPriceBotOutcome {
+        /// A price report got the prices, `price` is the one of the first pair in micro-units and `block` is the
+        /// block the result is processed in. `sent` and `failed` are the numbers of targets the report is delivered
+        /// to and failed to, both zero in a dry run
+        PriceReported {
+            price: u64,
+            block: u32,
+            sent: u32,
+            failed: u32,
+        },
+        /// A price report could not get the prices before `block`
+        ReportFailed { block: u32 },
+        /// A test notification is delivered or not
+        TestNotified { sent: bool },
+    }
+
+    bind_topic!(PastebinEvent, b"phala/pastebin/event");
+    /// The events of the Pastebin, sent before the `CommandAck` of the Command they report on
+    #[derive(Debug, Clone, Encode, Decode)]
+    pub enum PastebinEvent {
+        /// `account` is added to the readers of `granted` Posts by a `GrantAccessBulk`, at least one
+        AccessGranted { account: AccountId, granted: u32 },
+        /// `reader` reported a read of the Post `id` with a `RecordAccess` handled in `block`
+        ///
+        /// Only the reads the clients chose to report are noticed, see `RecordAccess`.
+        AccessNotice { id: String, reader: AccountId, block: u32 },
+        /// `purged` expired Posts are removed by a `PurgeExpired`
+        ExpiredPurged { purged: u32 },
+    }
+
+    bind_topic!(CommandAck, b"phala/contract/command_ack");
+    /// The result of a Command, sent by the contracts opting in to acknowledge their Commands
+    #[derive(Debug, Clone, Encode, Decode)]
+    pub struct CommandAck {
+        pub contract: contract::ContractId,
+        /// `blake2_256` of the SCALE-encoded Command
+        pub command_hash: [u8; 32],
+        pub result: Result<(), AckStatus>,
+    }
+
+    /// Why a Command failed, for the off-chain watchers of the `CommandAck`s
+    #[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+    pub struct AckStatus {
+        /// A stable HTTP-like status code of the error
+        pub code: u16,
+        /// The error with its details, for humans only since it may change between versions
+        pub message: String,
+    }
+
+    /// A post to be created in the Pastebin contract
+    ///
+    /// Start from `NewPost::new` and set the optional fields with the `with_*` methods, or fill the rest with
+    /// `..Default::default()`. The fields must only be appended, since `CreatePost` is encoded as them in order.
+    #[derive(Debug, Clone, Default, Encode, Decode)]
+    pub struct NewPost {
+        pub id: String,
+        pub owner: AccountId,
+        pub is_private: bool,
+        /// The accounts other than the owner allowed to read a private post
+        ///
+        /// The duplicates and the owner are dropped from the stored list.
+        pub readable_by: Vec<AccountId>,
+        pub content: String,
+        pub title: String,
+        /// Unix timestamp (in seconds) after which the post can no longer be read
+        pub expires_at: Option<u64>,
+        /// Custom key/value annotations of the post
+        pub metadata: BTreeMap<String, String>,
+        /// Set if `content` is the base64 ciphertext of the post, see `EncryptionMeta`
+        pub encryption: Option<EncryptionMeta>,
+        /// The id of the post this one replies to, which the owner must be able to read
+        pub parent_id: Option<String>,
+        /// How `content` encodes the bytes of the post
+        pub content_encoding: ContentEncoding,
+    }
+
+    impl NewPost {
+        /// A public post without title, expiry, metadata nor parent
+        pub fn new(id: String, owner: AccountId, content: String) -> Self {
+            NewPost {
+                id,
+                owner,
+                content,
+                ..Default::default()
+            }
+        }
+
+        /// Make the post private, readable by the owner and `readable_by` only
+        pub fn with_readers(mut self, readable_by: Vec<AccountId>) -> Self {
+            self.is_private = true;
+            self.readable_by = readable_by;
+            self
+        }
+
+        pub fn with_title(mut self, title: String) -> Self {
+            self.title = title;
+            self
+        }
+
+        pub fn with_expiry(mut self, expires_at: u64) -> Self {
+            self.expires_at = Some(expires_at);
+            self
+        }
+
+        pub fn with_metadata(mut self, key: String, value: String) -> Self {
+            self.metadata.insert(key, value);
+            self
+        }
+
+        pub fn with_encryption(mut self, encryption: EncryptionMeta) -> Self {
+            self.encryption = Some(encryption);
+            self
+        }
+
+        pub fn with_parent(mut self, parent_id: String) -> Self {
+            self.parent_id = Some(parent_id);
+            self
+        }
+
+        pub fn with_content_encoding(mut self, content_encoding: ContentEncoding) -> Self {
+            self.content_encoding = content_encoding;
+            self
+        }
+    }
+
+    /// How the text content of a Post encodes its bytes, for storing binary secrets such as keys or certificates
+    ///
+    /// The contract rejects a `Base64` or `Hex` content which doesn't decode. The content of an encrypted Post is the
+    /// base64 ciphertext whatever the encoding, which then applies to the decrypted bytes and can't be checked.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+    pub enum ContentEncoding {
+        /// Plain text
+        Utf8,
+        /// Standard base64 with padding
+        Base64,
+        /// Hexadecimal, with an even number of digits
+        Hex,
+    }
+
+    impl Default for ContentEncoding {
+        fn default() -> Self {
+            ContentEncoding::Utf8
+        }
+    }
+
+    /// The cipher of the content of an encrypted Post
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+    pub enum EncryptionAlgorithm {
+        Aes256Gcm,
+        XChaCha20Poly1305,
+    }
+
+    impl EncryptionAlgorithm {
+        /// The nonce size in bytes
+        pub fn nonce_len(&self) -> usize {
+            match self {
+                EncryptionAlgorithm::Aes256Gcm => 12,
+                EncryptionAlgorithm::XChaCha20Poly1305 => 24,
+            }
+        }
+
+        /// The authentication tag size in bytes, the minimum size of a ciphertext
+        pub fn tag_len(&self) -> usize {
+            16
+        }
+    }
+
+    /// How the content of an encrypted Post is sealed by its author
+    ///
+    /// The Pastebin contract stores the ciphertext and enforces the read permission as usual, but it never holds the
+    /// key, so the content stays secret even from the enclave. The client-side flow is:
+    ///
+    /// 1. The author generates a random content key and a random nonce of `algorithm.nonce_len()` bytes.
+    /// 2. The author encrypts the markdown with them, and sends the standard base64 of the ciphertext (with the tag) as
+    ///    the `content` of `CreatePost`, along with this `EncryptionMeta`.
+    /// 3. The content key is shared with the readers off-chain, e.g. in the URL fragment of the shared link, or wrapped
+    ///    to the public key of the reader named by `recipient_hint`.
+    /// 4. A reader queries the Post as usual and decrypts the base64-decoded `content` with the key and the nonce.
+    ///
+    /// The nonce must never be reused with the same key, so the content of an encrypted Post cannot be edited or
+    /// appended to. Create a new Post instead.
+    #[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+    pub struct EncryptionMeta {
+        pub algorithm: EncryptionAlgorithm,
+        pub nonce: Vec<u8>,
+        /// The public key the content key is wrapped to, to help the readers find the right key
+        pub recipient_hint: Option<[u8; 32]>,
+    }
+
+    /// What becomes of a Post once it expires
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+    pub enum ExpiryAction {
+        /// It can no longer be read but is kept in the storage, e.g. for audit
+        Hide,
+        /// It is removed from the storage
+        Delete,
+    }
+
+    impl Default for ExpiryAction {
+        fn default() -> Self {
+            ExpiryAction::Hide
+        }
     }
 
     // Bind on-chain PastebinCommand message to the PASTEBIN contract
@@ -263,15 +561,130 @@ pub mod messaging {
     #[derive(Debug, Clone, Encode, Decode)]
     pub enum PastebinCommand {
         /// Create Post
-        CreatePost {
+        ///
+        /// It has the same SCALE encoding as the former struct variant listing the fields of `NewPost` in order.
+        CreatePost(NewPost),
+        /// Set the maximum number of posts a single owner can hold
+        SetPostQuota { max_posts_per_owner: u32 },
+        /// Append to the content of an existing Post, only allowed for the post owner
+        AppendToPost { id: String, extra: String },
+        /// Create a batch of Posts atomically, either all of them or none are created
+        CreatePosts { posts: Vec<NewPost> },
+        /// Set a metadata entry of a Post, only allowed for the post owner
+        SetMetadata {
             id: String,
-            owner: AccountId,
-            is_private: bool,
-            readable_by: AccountId,
-            // readable_by: Vec<AccountId>,
-            content: String,
-            title: String,
+            key: String,
+            value: String,
+        },
+        /// Remove a metadata entry of a Post, only allowed for the post owner
+        RemoveMetadata { id: String, key: String },
+        /// Pause or resume the contract, a paused contract rejects all the other Commands
+        SetPaused { paused: bool },
+        /// Delete all the Posts of the sender
+        ClearMyPosts,
+        /// Replace the content of an existing Post, only allowed for the post owner
+        EditPost { id: String, content: String },
+        /// Set the lifetime in seconds of the new Posts created without an expiry, `None` to keep them forever
+        SetDefaultTtl { seconds: Option<u64> },
+        /// Change the id of a Post to an unused one, only allowed for the post owner
+        RenamePost { old_id: String, new_id: String },
+        /// Restore the SCALE-encoded Posts of the sender returned by the `ExportPosts` Query, either all of them or
+        /// none are imported
+        ///
+        /// The Posts whose id is taken are skipped if `skip_existing` is set, otherwise they reject the whole batch.
+        ImportPosts {
+            posts: Vec<Vec<u8>>,
+            skip_existing: bool,
+        },
+        /// Move a Post to the trash, where it is hidden from everyone until it is restored or purged, only allowed for
+        /// the post owner
+        DeletePost { id: String },
+        /// Take a Post back from the trash before it is purged, only allowed for the post owner
+        RestorePost { id: String },
+        /// Cap the lifetime in seconds of the new Posts, `None` to lift the cap, only allowed for the root account
+        ///
+        /// The Posts without an expiry get the longest one allowed. A longer explicit expiry is shortened to the cap,
+        /// or rejected if `reject_longer` is set.
+        SetMaxTtl {
+            seconds: Option<u64>,
+            reject_longer: bool,
         },
+        /// Copy a Post readable by the sender to an unused id, as a new Post of the sender
+        ///
+        /// The copy keeps the title, content and metadata of the source. It gets a fresh creation time, lifetime and
+        /// history, and the readers of a private source are not carried over.
+        ClonePost { source_id: String, new_id: String },
+        /// Mint a random token reading a private Post for anyone who presents it, only allowed for the post owner
+        ///
+        /// It replaces the previous token of the Post. The owner gets the token with the `QueryShareToken` Query.
+        CreateShareToken { id: String },
+        /// Invalidate the share token of a Post, only allowed for the post owner
+        RevokeShareToken { id: String },
+        /// Choose whether a Post is kept or removed once it expires, only allowed for the post owner
+        SetExpiryAction { id: String, action: ExpiryAction },
+        /// Hand the root account over to `new_owner`, only allowed for the current root account
+        TransferContractOwner { new_owner: AccountId },
+        /// Replace the words the public Posts must not contain, matched case-insensitively, only allowed for the root
+        /// account
+        SetBlockedWords { words: Vec<String> },
+        /// Add `account` to the readers of the private Posts among `ids` owned by the sender, the other ones are
+        /// skipped
+        ///
+        /// The number of Posts `account` is added to is reported with a `PastebinEvent::AccessGranted`, unless it is
+        /// none.
+        GrantAccessBulk { ids: Vec<String>, account: AccountId },
+        /// Count a read of a Post by the sender towards its `max_reads`, the owner's reads are not counted
+        ///
+        /// The Queries can't change the contract state, so the clients record each read of a Post limited by
+        /// `SetMaxReads` with this Command, which all the replicas apply in the same order.
+        RecordView { id: String },
+        /// Limit the number of recorded reads after which a Post can no longer be read, `None` for no limit, only
+        /// allowed for the post owner
+        SetMaxReads { id: String, max_reads: Option<u64> },
+        /// Pin or unpin a Post, only allowed for the post owner
+        ///
+        /// The pinned Posts are listed first by the `PostsByOwner` Query. An owner can pin a limited number of Posts,
+        /// and deleting a Post unpins it.
+        SetPinned { id: String, pinned: bool },
+        /// Choose whether the reads of a Post reported by `RecordAccess` are notified to the owner, only allowed for
+        /// the post owner
+        SetReadNotice { id: String, enabled: bool },
+        /// Report that the sender read a Post, notified with a `PastebinEvent::AccessNotice` if the owner enabled it
+        /// with `SetReadNotice`
+        ///
+        /// The access notices are best-effort and are not access auditing. The Posts are read by the Queries, which
+        /// are handled off-chain by a single worker and can neither change the contract state nor send egress
+        /// messages, so the contract never learns of a read by itself. A read is only noticed if the client
+        /// cooperates and submits this Command after it, which is then handled in a later block. Any other client
+        /// reads the Post without a trace. The reader and the block are taken from the Command origin and the block
+        /// it is handled in, not from the client. The egress messages are public, so the notice reveals to anyone
+        /// that the reader accessed the Post.
+        RecordAccess { id: String },
+        /// Require the owners of the new Posts to hold `min_balance` free balance on chain, `None` to let any account
+        /// post, only allowed for the root account
+        ///
+        /// It applies to `CreatePost`, `CreatePosts`, `ClonePost` and `ImportPosts`, and is checked against the chain
+        /// state of the block the Command is handled in.
+        SetMinBalanceToPost { min_balance: Option<u128> },
+        /// Remove the expired Posts at once, all of them for the root account and the sender's own ones for any other
+        /// account
+        ///
+        /// Unlike the sweep at the end of each block, it also removes the Posts set to `ExpiryAction::Hide`. The
+        /// number of removed Posts is reported with a `PastebinEvent::ExpiredPurged`.
+        PurgeExpired,
+        /// Move a Post out of the live ones into a compressed archive, only allowed for the post owner
+        ///
+        /// An archived Post keeps its id and counts towards the quota of its owner. It is left out of the listings,
+        /// its share token is revoked, and reading it fails with an `Archived` error until it is restored.
+        ArchivePost { id: String },
+        /// Restore an archived Post as it was archived, only allowed for the post owner
+        UnarchivePost { id: String },
+        /// Set the maximum number of accounts a Post can be shared with, only allowed for the root account
+        ///
+        /// It applies to the new readers added by `CreatePost`, `CreatePosts`, `ImportPosts` and `GrantAccessBulk`,
+        /// the Posts already shared more widely keep their readers. A share token reads a Post without growing its
+        /// readers.
+        SetMaxReaders { max_readers: u32 },
     }
 
     /// A fixed point number with 64 integer bits and 64 fractional bits.